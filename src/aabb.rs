@@ -2,6 +2,7 @@ use ray::Ray;
 use vec3::Vec3;
 use std::mem;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub struct AABB {
     min: Vec3,
@@ -53,6 +54,34 @@ impl AABB {
         self.max
     }
 
+    //Parametric range of the ray inside the box, clipped to [tmin, tmax]
+    pub fn intersect(&self, r: &Ray, tmin: f32, tmax: f32) -> Option<(f32, f32)> {
+        let mut t0 = tmin;
+        let mut t1 = tmax;
+        let axes = [(r.direction().x(), r.origin().x(), self.min.x(), self.max.x()),
+                    (r.direction().y(), r.origin().y(), self.min.y(), self.max.y()),
+                    (r.direction().z(), r.origin().z(), self.min.z(), self.max.z())];
+        for &(direction, origin, min_axis, max_axis) in axes.iter() {
+            let inv_d = 1.0 / direction;
+            let mut near = (min_axis - origin) * inv_d;
+            let mut far = (max_axis - origin) * inv_d;
+            if near > far {
+                mem::swap(&mut near, &mut far);
+            }
+            //NaN from rays parallel to a slab starting on its boundary keep the current range
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
     pub fn hit(&self, r: &Ray, tmin: f32, tmax: f32) -> bool {
         if !check_one_direction(r.direction().x(), r.origin().x(), self.min.x(), self.max.x(), tmin, tmax) {
             return false;
@@ -0,0 +1,135 @@
+use vec3::Vec3;
+use texture::ImageTexture;
+use sampler;
+use std::path::Path;
+use std::f32::consts::PI;
+
+//Shape of the opening of a lens, in units of its radius. Out of focus highlights take its shape.
+#[derive(Debug)]
+pub enum Aperture {
+    Circular,
+    //Regular polygon with a vertex at rotation radians. The blades bulge into arcs of the circle
+    //as curvature goes from 0 to 1.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+        curvature: f32,
+        area: f32
+    },
+    Image(ApertureImage)
+}
+
+//Grayscale transmission over the square around the unit disk, sampled texel by texel in
+//proportion to brightness
+#[derive(Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    weights: Vec<f32>,
+    cdf: Vec<f32>,
+    total: f32
+}
+
+fn random_in_unit_disk() -> Vec3 {
+    loop {
+        let p = 2.0 * Vec3::new(sampler::random(), sampler::random(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
+        if p.squared_length() < 1.0 {
+            return p;
+        }
+    }
+}
+
+//Distance from the center to the edge of a polygon aperture in direction phi
+fn polygon_radius(blades: u32, rotation: f32, curvature: f32, phi: f32) -> f32 {
+    let sector = 2.0 * PI / blades as f32;
+    let offset = (phi - rotation).rem_euclid(sector) - 0.5 * sector;
+    let straight = (0.5 * sector).cos() / offset.cos();
+    straight + curvature * (1.0 - straight)
+}
+
+impl Aperture {
+    pub fn polygon(blades: u32, rotation: f32, curvature: f32) -> Aperture {
+        if blades < 3 {
+            return Aperture::Circular;
+        }
+        let rotation = rotation.to_radians();
+        let curvature = curvature.clamp(0.0, 1.0);
+        //Half the integral of the squared radius over one sector, the sectors are alike
+        let steps = 256;
+        let sector = 2.0 * PI / blades as f32;
+        let area = blades as f32 * (0..steps).map(|i| {
+            let r = polygon_radius(blades, rotation, curvature, rotation + (i as f32 + 0.5) / steps as f32 * sector);
+            0.5 * r * r * sector / steps as f32
+        }).sum::<f32>();
+        Aperture::Polygon {
+            blades,
+            rotation,
+            curvature,
+            area
+        }
+    }
+
+    //The image is stretched over the square the aperture circle fits in
+    pub fn load(path: &Path) -> Result<Aperture, String> {
+        let texture = ImageTexture::load(path, false)?;
+        let (width, height, pixels) = texture.pixels();
+        let weights: Vec<f32> = pixels.iter().map(|pixel| (pixel.x() + pixel.y() + pixel.z()) / 3.0).collect();
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut total = 0.0;
+        for weight in weights.iter() {
+            total += weight;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return Err(format!("{}: aperture image is black", path.display()));
+        }
+        Ok(Aperture::Image(ApertureImage {
+            width,
+            height,
+            weights,
+            cdf,
+            total
+        }))
+    }
+
+    //Point on the aperture as x and y of the returned vector
+    pub fn sample(&self) -> Vec3 {
+        match *self {
+            Aperture::Circular => random_in_unit_disk(),
+            Aperture::Polygon { .. } => loop {
+                let p = random_in_unit_disk();
+                if self.pdf(p.x(), p.y()) > 0.0 {
+                    return p;
+                }
+            },
+            Aperture::Image(ref image) => {
+                let target = sampler::random() * image.total;
+                let index = image.cdf.partition_point(|&c| c <= target).min(image.cdf.len() - 1);
+                let column = (index % image.width) as f32 + sampler::random();
+                let row = (index / image.width) as f32 + sampler::random();
+                Vec3::new(2.0 * column / image.width as f32 - 1.0, 1.0 - 2.0 * row / image.height as f32, 0.0)
+            }
+        }
+    }
+
+    //Density over the area of the aperture with which sample returns (x, y)
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let r2 = x * x + y * y;
+        match *self {
+            Aperture::Circular => if r2 <= 1.0 { 1.0 / PI } else { 0.0 },
+            Aperture::Polygon { blades, rotation, curvature, area } => {
+                let radius = polygon_radius(blades, rotation, curvature, y.atan2(x));
+                if r2 <= radius * radius { 1.0 / area } else { 0.0 }
+            },
+            Aperture::Image(ref image) => {
+                if x.abs() >= 1.0 || y.abs() >= 1.0 {
+                    return 0.0;
+                }
+                let column = ((x + 1.0) * 0.5 * image.width as f32) as usize;
+                let row = ((1.0 - y) * 0.5 * image.height as f32) as usize;
+                let texel_area = 4.0 / (image.width * image.height) as f32;
+                image.weights[row.min(image.height - 1) * image.width + column.min(image.width - 1)] / (image.total * texel_area)
+            }
+        }
+    }
+}
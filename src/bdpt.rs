@@ -0,0 +1,405 @@
+use vec3::Vec3;
+use ray::Ray;
+use camera::Camera;
+use film::Film;
+use hitable::Hitable;
+use light::{LightList, pdf_emission_direction};
+use material::Material;
+use texture::TexCoord;
+
+//Bidirectional path tracing following Veach's formulation: a camera subpath and a light subpath
+//are traced independently, every pair of vertices is connected and the resulting strategies
+//are combined with the balance heuristic. Densities are stored in area measure.
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface
+}
+
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Vec3,
+    //Zero for scattering events inside participating media
+    normal: Vec3,
+    uv: TexCoord,
+    //Direction towards the previous vertex of the subpath
+    wo: Vec3,
+    beta: Vec3,
+    material: Option<&'a (dyn Material + Sync)>,
+    delta: bool,
+    pdf_fwd: f32,
+    pdf_rev: f32
+}
+
+impl<'a> Vertex<'a> {
+    fn endpoint(kind: VertexKind, p: Vec3, normal: Vec3, beta: Vec3, pdf_fwd: f32) -> Vertex<'a> {
+        Vertex {
+            kind,
+            p,
+            normal,
+            uv: TexCoord::new(0.0, 0.0),
+            wo: Vec3::zero_vector(),
+            beta,
+            material: None,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        self.normal.squared_length() > 0.0
+    }
+
+    fn abs_cos(&self, w: Vec3) -> f32 {
+        if self.on_surface() {
+            self.normal.dot(w).abs()
+        } else {
+            1.0
+        }
+    }
+
+    fn is_emitter(&self) -> bool {
+        match self.material {
+            Some(material) => self.kind == VertexKind::Surface && material.is_emissive(),
+            None => false
+        }
+    }
+
+    fn le(&self) -> Vec3 {
+        match self.material {
+            Some(material) => material.emitted(self.uv.u, self.uv.v, &self.p),
+            None => Vec3::zero_vector()
+        }
+    }
+
+    //Light vertices already carry Le / pdf in beta, emission is the same in every direction
+    fn f(&self, next: &Vertex) -> Vec3 {
+        match (self.kind, self.material) {
+            (VertexKind::Light, _) => Vec3::new(1.0, 1.0, 1.0),
+            (VertexKind::Surface, Some(material)) => {
+                let wi = Vec3::unit_vector(next.p - self.p);
+                material.eval(self.wo, wi, self.p, self.normal, self.uv)
+            },
+            _ => Vec3::zero_vector()
+        }
+    }
+
+    //Turns a solid angle density at this vertex into an area density at next
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let w = next.p - self.p;
+        let dist2 = w.squared_length();
+        if dist2 == 0.0 {
+            return 0.0;
+        }
+        pdf * next.abs_cos(w / dist2.sqrt()) / dist2
+    }
+
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let w = Vec3::unit_vector(next.p - self.p);
+        self.convert_density(pdf_emission_direction(self.normal, w), next)
+    }
+
+    //Area density of sampling next when the path arrived at this vertex from prev
+    fn pdf(&self, camera: &dyn Camera, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        match self.kind {
+            VertexKind::Light => self.pdf_light(next),
+            VertexKind::Camera => {
+                let (_, pdf_dir) = camera.pdf_importance(&Ray::new(self.p, next.p - self.p));
+                self.convert_density(pdf_dir, next)
+            },
+            VertexKind::Surface => {
+                let material = match self.material {
+                    Some(material) => material,
+                    None => return 0.0
+                };
+                let wp = match prev {
+                    Some(prev) => Vec3::unit_vector(prev.p - self.p),
+                    None => self.wo
+                };
+                let wn = Vec3::unit_vector(next.p - self.p);
+                self.convert_density(material.pdf(wp, wn, self.p, self.normal, self.uv), next)
+            }
+        }
+    }
+}
+
+fn unoccluded(world: &dyn Hitable, from: Vec3, to: Vec3) -> bool {
+    let d = to - from;
+    let dist = d.length();
+    world.hit(0.001, dist - 0.001, &Ray::new(from, d / dist)).is_none()
+}
+
+fn geometry_term(world: &dyn Hitable, a: &Vertex, b: &Vertex) -> f32 {
+    let d = b.p - a.p;
+    let dist2 = d.squared_length();
+    if dist2 == 0.0 || !unoccluded(world, a.p, b.p) {
+        return 0.0;
+    }
+    let w = d / dist2.sqrt();
+    a.abs_cos(w) * b.abs_cos(w) / dist2
+}
+
+fn random_walk<'a>(world: &'a (dyn Hitable + Sync), mut ray: Ray, mut beta: Vec3, pdf: f32, max_depth: u32, path: &mut Vec<Vertex<'a>>) {
+    if max_depth == 0 {
+        return;
+    }
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+
+    while let Some(hit) = world.hit(0.001, f32::MAX, &ray) {
+        let prev_index = path.len() - 1;
+        let wo = Vec3::unit_vector(-ray.direction());
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            p: hit.p,
+            normal: hit.normal,
+            uv: hit.uv,
+            wo,
+            beta,
+            material: Some(hit.material),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0
+        };
+        vertex.pdf_fwd = path[prev_index].convert_density(pdf_fwd, &vertex);
+
+        bounces += 1;
+        if bounces >= max_depth {
+            path.push(vertex);
+            break;
+        }
+
+        let scatter_rec = match hit.scatter(&ray) {
+            Some(scatter_rec) => scatter_rec,
+            None => {
+                path.push(vertex);
+                break;
+            }
+        };
+        let wi = Vec3::unit_vector(scatter_rec.scattered.direction());
+        let pdf_rev = if scatter_rec.is_specular {
+            vertex.delta = true;
+            pdf_fwd = 0.0;
+            0.0
+        } else {
+            pdf_fwd = hit.material.pdf(wo, wi, hit.p, hit.normal, hit.uv);
+            hit.material.pdf(wi, wo, hit.p, hit.normal, hit.uv)
+        };
+        beta = beta * scatter_rec.attenuation;
+        path[prev_index].pdf_rev = vertex.convert_density(pdf_rev, &path[prev_index]);
+        path.push(vertex);
+
+        if beta.max_component() <= 0.0 {
+            break;
+        }
+        ray = scatter_rec.scattered;
+    }
+}
+
+fn camera_subpath<'a>(camera: &dyn Camera, world: &'a (dyn Hitable + Sync), s: f32, t: f32, max_depth: u32) -> Vec<Vertex<'a>> {
+    let mut path = Vec::with_capacity(max_depth as usize + 2);
+    let ray = match camera.get_ray(s, t) {
+        Some(ray) => ray,
+        None => return path
+    };
+    let (_, pdf_dir) = camera.pdf_importance(&ray);
+    if pdf_dir <= 0.0 {
+        return path;
+    }
+    let one = Vec3::new(1.0, 1.0, 1.0);
+    let mut vertex = Vertex::endpoint(VertexKind::Camera, ray.origin(), camera.forward(), one, 0.0);
+    //Strategies connecting light paths to a camera that doesn't support it get no weight
+    vertex.delta = !camera.connectable();
+    path.push(vertex);
+    random_walk(world, ray, one, pdf_dir, max_depth + 1, &mut path);
+    path
+}
+
+fn light_subpath<'a>(lights: &LightList, world: &'a (dyn Hitable + Sync), max_depth: u32) -> Vec<Vertex<'a>> {
+    let mut path = Vec::with_capacity(max_depth as usize + 1);
+    let emission = match lights.sample_emission() {
+        Some(emission) => emission,
+        None => return path
+    };
+    if emission.pdf_dir <= 0.0 {
+        return path;
+    }
+    path.push(Vertex::endpoint(VertexKind::Light, emission.ray.origin(), emission.normal, emission.le / emission.pdf_pos, emission.pdf_pos));
+    let cos = emission.normal.dot(emission.ray.direction()).abs();
+    let beta = emission.le * (cos / (emission.pdf_pos * emission.pdf_dir));
+    random_walk(world, emission.ray, beta, emission.pdf_dir, max_depth, &mut path);
+    path
+}
+
+//Balance heuristic weight of the strategy using s light and t camera vertices. The endpoint
+//densities depend on the connection, they are patched in place and restored afterwards.
+fn mis_weight(camera: &dyn Camera, lights: &LightList, light_path: &mut [Vertex], camera_path: &mut [Vertex], s: usize, t: usize) -> f32 {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    let pt_rev = {
+        let pt = &camera_path[t - 1];
+        if s > 0 {
+            let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+            light_path[s - 1].pdf(camera, qs_minus, pt)
+        } else {
+            lights.pdf_position()
+        }
+    };
+    let pt_minus_rev = if t > 1 {
+        let pt = &camera_path[t - 1];
+        let pt_minus = &camera_path[t - 2];
+        Some(if s > 0 {
+            pt.pdf(camera, Some(&light_path[s - 1]), pt_minus)
+        } else {
+            pt.pdf_light(pt_minus)
+        })
+    } else {
+        None
+    };
+    let qs_rev = if s > 0 {
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+        Some(camera_path[t - 1].pdf(camera, pt_minus, &light_path[s - 1]))
+    } else {
+        None
+    };
+    let qs_minus_rev = if s > 1 {
+        Some(light_path[s - 1].pdf(camera, Some(&camera_path[t - 1]), &light_path[s - 2]))
+    } else {
+        None
+    };
+
+    let saved_camera = (camera_path[t - 1], if t > 1 { Some(camera_path[t - 2]) } else { None });
+    let saved_light = (if s > 0 { Some(light_path[s - 1]) } else { None }, if s > 1 { Some(light_path[s - 2]) } else { None });
+
+    camera_path[t - 1].pdf_rev = pt_rev;
+    camera_path[t - 1].delta = false;
+    if let Some(pdf) = pt_minus_rev {
+        camera_path[t - 2].pdf_rev = pdf;
+    }
+    if let Some(pdf) = qs_rev {
+        light_path[s - 1].pdf_rev = pdf;
+        light_path[s - 1].delta = false;
+    }
+    if let Some(pdf) = qs_minus_rev {
+        light_path[s - 2].pdf_rev = pdf;
+    }
+
+    let remap0 = |f: f32| if f != 0.0 { f } else { 1.0 };
+    let mut sum_ri = 0.0;
+
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        ri *= remap0(camera_path[i].pdf_rev) / remap0(camera_path[i].pdf_fwd);
+        if !camera_path[i].delta && !camera_path[i - 1].delta {
+            sum_ri += ri;
+        }
+    }
+
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap0(light_path[i].pdf_rev) / remap0(light_path[i].pdf_fwd);
+        let delta_light_vertex = i > 0 && light_path[i - 1].delta;
+        if !light_path[i].delta && !delta_light_vertex {
+            sum_ri += ri;
+        }
+    }
+
+    camera_path[t - 1] = saved_camera.0;
+    if let Some(vertex) = saved_camera.1 {
+        camera_path[t - 2] = vertex;
+    }
+    if let Some(vertex) = saved_light.0 {
+        light_path[s - 1] = vertex;
+    }
+    if let Some(vertex) = saved_light.1 {
+        light_path[s - 2] = vertex;
+    }
+
+    1.0 / (1.0 + sum_ri)
+}
+
+//Unweighted contribution of strategy (s, t) and the film position for light tracing (t = 1)
+fn connect(camera: &dyn Camera, world: &dyn Hitable, lights: &LightList, light_path: &mut [Vertex], camera_path: &mut [Vertex], s: usize, t: usize) -> (Vec3, Option<(f32, f32)>) {
+    let mut raster = None;
+    let saved_camera_vertex = camera_path[0];
+
+    let l = if s == 0 {
+        let pt = &camera_path[t - 1];
+        if !pt.is_emitter() {
+            return (Vec3::zero_vector(), None);
+        }
+        pt.le() * pt.beta
+    } else if t == 1 {
+        let qs = light_path[s - 1];
+        if qs.delta {
+            return (Vec3::zero_vector(), None);
+        }
+        let sample = match camera.sample_importance(qs.p) {
+            Some(sample) => sample,
+            None => return (Vec3::zero_vector(), None)
+        };
+        if sample.pdf <= 0.0 || sample.importance <= 0.0 {
+            return (Vec3::zero_vector(), None);
+        }
+        let sampled = Vertex::endpoint(VertexKind::Camera, sample.lens_point, camera.forward(), Vec3::new(1.0, 1.0, 1.0) * (sample.importance / sample.pdf), 0.0);
+        let mut l = qs.beta * qs.f(&sampled) * sampled.beta * qs.abs_cos(sample.wi);
+        if l.max_component() > 0.0 && !unoccluded(world, qs.p, sample.lens_point) {
+            l = Vec3::zero_vector();
+        }
+        camera_path[0] = sampled;
+        raster = Some((sample.s, sample.t));
+        l
+    } else {
+        let qs = &light_path[s - 1];
+        let pt = &camera_path[t - 1];
+        if qs.delta || pt.delta {
+            return (Vec3::zero_vector(), None);
+        }
+        let l = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+        if l.max_component() > 0.0 {
+            l * geometry_term(world, qs, pt)
+        } else {
+            l
+        }
+    };
+
+    let l = if l.max_component() > 0.0 {
+        l * mis_weight(camera, lights, light_path, camera_path, s, t)
+    } else {
+        l
+    };
+    camera_path[0] = saved_camera_vertex;
+    (l, raster)
+}
+
+pub fn color(camera: &dyn Camera, world: &(dyn Hitable + Sync), lights: &LightList, film: &Film, s: f32, t: f32, max_depth: u32) -> Vec3 {
+    let mut camera_path = camera_subpath(camera, world, s, t, max_depth);
+    let mut light_path = light_subpath(lights, world, max_depth);
+    let mut radiance = Vec3::zero_vector();
+
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            let depth = (s + t) as i64 - 2;
+            //Emitters seen directly are counted by s = 0, t = 2
+            if depth < 0 || depth > max_depth as i64 || (s == 1 && t == 1) {
+                continue;
+            }
+            let (l, raster) = connect(camera, world, lights, &mut light_path, &mut camera_path, s, t);
+            if t == 1 {
+                if let Some((s, t)) = raster {
+                    film.add_splat(s, t, l);
+                }
+            } else {
+                radiance = radiance + l;
+            }
+        }
+    }
+
+    radiance
+}
@@ -0,0 +1,48 @@
+use vec3::Vec3;
+use texture::Texture;
+use std::sync::Arc;
+
+//Surface detail perturbing the shading normal of a primitive, independent of its material
+pub enum BumpMap {
+    //Tangent space normals encoded as colors, x along increasing u and y along increasing v
+    Normal(Arc<dyn Texture + Send + Sync>),
+    //Height field read from the first channel and scaled to world units
+    Height(Arc<dyn Texture + Send + Sync>, f32)
+}
+
+impl BumpMap {
+    //dpdu and dpdv are the derivatives of the surface position with respect to the texture
+    //coordinates, normal is the geometric normal. The result lies on the side of normal.
+    pub fn shading_normal(&self, u: f32, v: f32, p: Vec3, normal: Vec3, dpdu: Vec3, dpdv: Vec3) -> Vec3 {
+        let shading = match *self {
+            BumpMap::Normal(ref texture) => {
+                let tangent = dpdu - normal * normal.dot(dpdu);
+                if tangent.squared_length() == 0.0 {
+                    return normal;
+                }
+                let tangent = Vec3::unit_vector(tangent);
+                //Mirrored texture coordinates flip the bitangent
+                let bitangent = normal.cross(tangent);
+                let bitangent = if bitangent.dot(dpdv) < 0.0 { -bitangent } else { bitangent };
+                let color = texture.value(u, v, &p);
+                (2.0 * color.x() - 1.0) * tangent + (2.0 * color.y() - 1.0) * bitangent + (2.0 * color.z() - 1.0) * normal
+            },
+            BumpMap::Height(ref texture, scale) => {
+                //Forward differences of the displaced surface (Blinn 1978)
+                const DELTA: f32 = 0.0005;
+                let height = |u: f32, v: f32| scale * texture.value(u, v, &p).x();
+                let displacement = height(u, v);
+                let dhdu = (height(u + DELTA, v) - displacement) / DELTA;
+                let dhdv = (height(u, v + DELTA) - displacement) / DELTA;
+                let n = (dpdu + dhdu * normal).cross(dpdv + dhdv * normal);
+                if n.dot(normal) < 0.0 { -n } else { n }
+            }
+        };
+        //Keep the geometric side so that rays do not scatter through the surface
+        if shading.dot(normal) <= 0.0 {
+            normal
+        } else {
+            Vec3::unit_vector(shading)
+        }
+    }
+}
@@ -1,10 +1,68 @@
 use vec3::Vec3;
-use ray::Ray;
+use ray::{Ray, RayDifferential};
+use aperture::Aperture;
 
-extern crate rand;
+//Maps film positions (s, t) in [0, 1], t growing upwards, to rays leaving the camera
+pub trait Camera {
+    //None for film positions outside the image, as around the circle of a fisheye
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray>;
+    //Direction the camera looks in, the normal of its endpoint in bidirectional methods
+    fn forward(&self) -> Vec3;
+    //Returns (positional, directional) densities with which get_ray generates r
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32);
+    //Light tracing connections to the camera, cameras that can't be reached return None
+    fn sample_importance(&self, _point: Vec3) -> Option<CameraSample> {
+        None
+    }
+    //Whether sample_importance can connect light paths to the camera, bidirectional methods
+    //leave the strategies relying on it out of their weights otherwise
+    fn connectable(&self) -> bool {
+        false
+    }
+    //Fraction of the light reaching film position (s, t), applied to the finished film
+    fn vignetting(&self, _s: f32, _t: f32) -> f32 {
+        1.0
+    }
+    //Whether rays depend on the wavelength, spectral rendering then traces the hero wavelength only
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+}
+
+//Lens and exposure settings of a real camera. Lengths on the camera are in millimeters, the
+//scene is measured in units of scene_unit meters.
+pub struct PhysicalSettings {
+    pub focal_length: f32,
+    //Width of the sensor, its height follows the aspect ratio of the image
+    pub sensor_width: f32,
+    pub f_number: f32,
+    //Exposure time in seconds
+    pub shutter: f32,
+    pub iso: f32,
+    pub scene_unit: f32
+}
+
+impl PhysicalSettings {
+    pub fn vfov(&self, aspect: f32) -> f32 {
+        let sensor_height = self.sensor_width / aspect;
+        2.0 * (sensor_height / (2.0 * self.focal_length)).atan().to_degrees()
+    }
+
+    //Diameter of the entrance pupil in scene units
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number / 1000.0 / self.scene_unit
+    }
+
+    //Photometric exposure for radiance in cd/m^2, saturation based so that a luminance of
+    //1.2 * 2^EV100 reaches white
+    pub fn exposure(&self) -> f32 {
+        let ev100 = (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2();
+        1.0 / (1.2 * 2f32.powf(ev100))
+    }
+}
 
 #[derive(Debug)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     origin: Vec3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
@@ -12,20 +70,78 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    lens_radius: f32
+    lens_radius: f32,
+    aperture: Aperture,
+    //Offset of the lens barrel at the image corners in lens radii, it clips the aperture off axis
+    cat_eye: f32,
+    //Natural cos^4 falloff of the light reaching the film away from the axis
+    natural_vignetting: bool,
+    focus_dist: f32,
+    image_area: f32,
+    //Film offsets of the differential rays, none when camera rays carry no differentials
+    pixel_spacing: Option<(f32, f32)>
+}
+
+//Parallel rays leaving a view plane through lookfrom, without perspective foreshortening
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    w: Vec3,
+    pixel_spacing: Option<(f32, f32)>
+}
+
+//Importance arriving at the camera from a point in the scene, used by light tracing
+pub struct CameraSample {
+    pub wi: Vec3,
+    pub dist: f32,
+    pub pdf: f32,
+    pub importance: f32,
+    pub s: f32,
+    pub t: f32,
+    pub lens_point: Vec3
 }
 
-fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = 2.0 * Vec3::new(rand::random::<f32>(), rand::random::<f32>(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-        if p.squared_length() < 1.0 {
-            return p;
+//Orthonormal basis of a camera, directions in it have x right, y up and z forward
+#[derive(Debug)]
+pub struct CameraFrame {
+    pub origin: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub forward: Vec3
+}
+
+impl CameraFrame {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> CameraFrame {
+        let forward = Vec3::unit_vector(lookat - lookfrom);
+        let right = Vec3::unit_vector(forward.cross(vup));
+        CameraFrame {
+            origin: lookfrom,
+            right,
+            up: right.cross(forward),
+            forward
         }
     }
+
+    pub fn to_world(&self, d: Vec3) -> Vec3 {
+        d.x() * self.right + d.y() * self.up + d.z() * self.forward
+    }
+
+    pub fn to_local(&self, d: Vec3) -> Vec3 {
+        Vec3::new(d.dot(self.right), d.dot(self.up), d.dot(self.forward))
+    }
 }
 
-impl Camera {
-    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32) -> Camera {
+//Film offsets of the differential rays. Several samples per pixel already average part of the
+//footprint, so the spacing narrows with more of them (pbrt).
+pub fn pixel_spacing(width: u32, height: u32, samples_per_pixel: usize) -> (f32, f32) {
+    let scale = (1.0 / (samples_per_pixel as f32).sqrt()).max(0.125);
+    (scale / width as f32, scale / height as f32)
+}
+
+impl PerspectiveCamera {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32) -> PerspectiveCamera {
         let theta = vfov.to_radians();
         let half_height = (theta/2.0).tan();
         let half_width = aspect * half_height;
@@ -33,7 +149,7 @@ impl Camera {
         let w = Vec3::unit_vector(lookfrom - lookat);
         let u = Vec3::unit_vector(vup.cross(w));
         let v = w.cross(u);
-        Camera {
+        PerspectiveCamera {
             origin: lookfrom,
             lower_left_corner: lookfrom - half_width*focus_dist*u - half_height*focus_dist*v - focus_dist*w,
             horizontal: 2.0 * half_width * focus_dist * u,
@@ -41,13 +157,237 @@ impl Camera {
             u,
             v,
             w,
-            lens_radius: aperture / 2.0
+            lens_radius: aperture / 2.0,
+            aperture: Aperture::Circular,
+            cat_eye: 0.0,
+            natural_vignetting: false,
+            focus_dist,
+            image_area: 4.0 * half_width * half_height,
+            pixel_spacing: None
+        }
+    }
+
+    //Field of view and lens size of a real lens focused at focus_dist
+    pub fn physical(lookfrom: Vec3, lookat: Vec3, vup: Vec3, aspect: f32, focus_dist: f32, settings: &PhysicalSettings) -> PerspectiveCamera {
+        PerspectiveCamera::new(lookfrom, lookat, vup, settings.vfov(aspect), aspect, settings.aperture(), focus_dist)
+    }
+
+    //Camera rays carry differentials towards the neighbouring pixels
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> PerspectiveCamera {
+        PerspectiveCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    pub fn with_aperture(self, aperture: Aperture) -> PerspectiveCamera {
+        PerspectiveCamera {
+            aperture,
+            ..self
+        }
+    }
+
+    pub fn with_cat_eye(self, cat_eye: f32) -> PerspectiveCamera {
+        PerspectiveCamera {
+            cat_eye,
+            ..self
+        }
+    }
+
+    pub fn with_natural_vignetting(self, natural_vignetting: bool) -> PerspectiveCamera {
+        PerspectiveCamera {
+            natural_vignetting,
+            ..self
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    //Density over the lens area of the points rays leave from. A pinhole camera is treated as a
+    //lens with unit area so importance stays finite.
+    fn lens_pdf(&self, lens_point: Vec3) -> f32 {
+        if self.lens_radius <= 0.0 {
+            return 1.0;
+        }
+        let offset = (lens_point - self.origin) / self.lens_radius;
+        self.aperture.pdf(offset.dot(self.u), offset.dot(self.v)) / (self.lens_radius * self.lens_radius)
+    }
+
+    //Whether the barrel blocks rays from the lens point to film position (s, t). Seen from off
+    //axis it is a disk as large as the aperture, shifted towards the image edge, which leaves a
+    //cat's eye shaped opening.
+    fn vignetted(&self, lens_point: Vec3, s: f32, t: f32) -> bool {
+        if self.cat_eye <= 0.0 || self.lens_radius <= 0.0 {
+            return false;
+        }
+        let width = self.horizontal.length();
+        let height = self.vertical.length();
+        let diagonal = (width * width + height * height).sqrt();
+        let barrel_x = self.cat_eye * (2.0 * s - 1.0) * width / diagonal;
+        let barrel_y = self.cat_eye * (2.0 * t - 1.0) * height / diagonal;
+        let offset = (lens_point - self.origin) / self.lens_radius;
+        let x = offset.dot(self.u) - barrel_x;
+        let y = offset.dot(self.v) - barrel_y;
+        x * x + y * y > 1.0
+    }
+
+    //Film position (s, t) a ray leaving the lens would have been generated from
+    fn raster_position(&self, r: &Ray) -> Option<(f32, f32)> {
+        let direction = Vec3::unit_vector(r.direction());
+        let cos_theta = direction.dot(self.forward());
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let lens_offset = r.origin() - self.origin;
+        let focus_point = self.origin + (self.focus_dist / cos_theta) * direction + lens_offset;
+        let from_corner = focus_point - self.lower_left_corner;
+        let s = from_corner.dot(self.horizontal) / self.horizontal.squared_length();
+        let t = from_corner.dot(self.vertical) / self.vertical.squared_length();
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+        Some((s, t))
+    }
+
+    pub fn importance(&self, r: &Ray) -> Option<(f32, f32, f32)> {
+        let (s, t) = self.raster_position(r)?;
+        if self.vignetted(r.origin(), s, t) {
+            return None;
+        }
+        let cos_theta = Vec3::unit_vector(r.direction()).dot(self.forward());
+        let cos2_theta = cos_theta * cos_theta;
+        Some((self.lens_pdf(r.origin()) / (self.image_area * cos2_theta * cos2_theta), s, t))
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32) {
+        match self.raster_position(r) {
+            Some((s, t)) if !self.vignetted(r.origin(), s, t) => {},
+            _ => return (0.0, 0.0)
+        }
+        let cos_theta = Vec3::unit_vector(r.direction()).dot(self.forward());
+        (self.lens_pdf(r.origin()), 1.0 / (self.image_area * cos_theta * cos_theta * cos_theta))
+    }
+
+    fn sample_importance(&self, point: Vec3) -> Option<CameraSample> {
+        let rd = self.lens_radius * self.aperture.sample();
+        let lens_point = self.origin + self.u * rd.x() + self.v * rd.y();
+        let to_lens = lens_point - point;
+        let dist = to_lens.length();
+        let wi = to_lens / dist;
+        let cos_lens = wi.dot(self.forward()).abs();
+        if cos_lens == 0.0 {
+            return None;
+        }
+        let (importance, s, t) = self.importance(&Ray::new(lens_point, -wi))?;
+        Some(CameraSample {
+            wi,
+            dist,
+            pdf: self.lens_pdf(lens_point) * dist * dist / cos_lens,
+            importance,
+            s,
+            t,
+            lens_point
+        })
+    }
+
+    fn connectable(&self) -> bool {
+        true
+    }
+
+    //By the angle of the ray from the lens center to the film position
+    fn vignetting(&self, s: f32, t: f32) -> f32 {
+        if !self.natural_vignetting {
+            return 1.0;
+        }
+        let direction = Vec3::unit_vector(self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin);
+        let cos_theta = direction.dot(self.forward());
+        cos_theta * cos_theta * cos_theta * cos_theta
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let rd = self.lens_radius * self.aperture.sample();
         let offset = self.u * rd.x() + self.v*rd.y();
-        Ray::new(self.origin + offset, self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset)
+        if self.vignetted(self.origin + offset, s, t) {
+            return None;
+        }
+        let direction = |s: f32, t: f32| self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset;
+        let ray = Ray::new(self.origin + offset, direction(s, t));
+        Some(match self.pixel_spacing {
+            //Through the same lens point, so they meet the main ray on the focal plane
+            Some((ds, dt)) => ray.with_differential(RayDifferential {
+                rx_origin: ray.origin(),
+                rx_direction: direction(s + ds, t),
+                ry_origin: ray.origin(),
+                ry_direction: direction(s, t + dt)
+            }),
+            None => ray
+        })
+    }
+}
+
+impl OrthographicCamera {
+    //width is the extent of the view plane along u in scene units, its height follows the aspect
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, width: f32, aspect: f32) -> OrthographicCamera {
+        let half_width = width / 2.0;
+        let half_height = half_width / aspect;
+
+        let w = Vec3::unit_vector(lookfrom - lookat);
+        let u = Vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+        OrthographicCamera {
+            lower_left_corner: lookfrom - half_width*u - half_height*v,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            w,
+            pixel_spacing: None
+        }
+    }
+
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> OrthographicCamera {
+        OrthographicCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    fn view_area(&self) -> f32 {
+        self.horizontal.length() * self.vertical.length()
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    //Uniform over the view plane. A single direction leaves each point, its density is a delta
+    //reported as one, which also keeps light paths from connecting to the camera.
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32) {
+        let from_corner = r.origin() - self.lower_left_corner;
+        let s = from_corner.dot(self.horizontal) / self.horizontal.squared_length();
+        let t = from_corner.dot(self.vertical) / self.vertical.squared_length();
+        if Vec3::unit_vector(r.direction()).dot(self.forward()) < 0.999 || !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+            return (0.0, 0.0);
+        }
+        (1.0 / self.view_area(), 1.0)
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let origin = |s: f32, t: f32| self.lower_left_corner + s*self.horizontal + t*self.vertical;
+        let ray = Ray::new(origin(s, t), self.forward());
+        Some(match self.pixel_spacing {
+            //Parallel to the main ray, offset by a pixel on the view plane
+            Some((ds, dt)) => ray.with_differential(RayDifferential {
+                rx_origin: origin(s + ds, t),
+                rx_direction: self.forward(),
+                ry_origin: origin(s, t + dt),
+                ry_direction: self.forward()
+            }),
+            None => ray
+        })
     }
 }
@@ -0,0 +1,43 @@
+use vec3::Vec3;
+use texture::Texture;
+
+//Radiance arriving from infinitely far away, looked up by direction in an equirectangular
+//(latitude-longitude) image with +y up and -z at its center. Rays leaving the scene collect it.
+pub struct Environment {
+    texture: Box<dyn Texture + Sync>,
+    //Turn about the y axis as a fraction of a full turn
+    rotation: f32,
+    intensity: f32
+}
+
+impl Environment {
+    pub fn new(texture: Box<dyn Texture + Sync>) -> Environment {
+        Environment {
+            texture,
+            rotation: 0.0,
+            intensity: 1.0
+        }
+    }
+
+    //Turns the environment counterclockwise about the y axis seen from above
+    pub fn with_rotation(self, degrees: f32) -> Environment {
+        Environment {
+            rotation: degrees / 360.0,
+            ..self
+        }
+    }
+
+    pub fn with_intensity(self, intensity: f32) -> Environment {
+        Environment {
+            intensity,
+            ..self
+        }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let d = Vec3::unit_vector(direction);
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * std::f32::consts::PI) + self.rotation;
+        let v = 1.0 - d.y().clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        self.intensity * self.texture.value(u, v, &d)
+    }
+}
@@ -0,0 +1,135 @@
+use vec3::Vec3;
+use std::path::Path;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+extern crate png;
+
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vec3>,
+    //Three channels per pixel holding f32 bits, so splats from all threads add up without locking
+    splats: Vec<AtomicU32>,
+    //Scale from scene radiance to the displayed range, applied when writing
+    exposure: f32
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let size = (width * height) as usize;
+        Film {
+            width,
+            height,
+            pixels: vec![Vec3::zero_vector(); size],
+            splats: (0..3 * size).map(|_| AtomicU32::new(0)).collect(),
+            exposure: 1.0
+        }
+    }
+
+    pub fn with_exposure(self, exposure: f32) -> Film {
+        Film {
+            exposure,
+            ..self
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    //Pixel coordinates follow the camera convention, y = 0 is the bottom row of the image
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((self.height - 1 - y) * self.width + x) as usize
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: Vec3) {
+        let index = self.index(x, y);
+        self.pixels[index] = color;
+    }
+
+    //Adds a contribution at film position (s, t) in [0, 1]^2, independent of the pixel being rendered
+    pub fn add_splat(&self, s: f32, t: f32, color: Vec3) {
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return;
+        }
+        let x = (s * self.width as f32) as u32;
+        let y = (t * self.height as f32) as u32;
+        let index = self.index(x.min(self.width - 1), y.min(self.height - 1));
+        for (channel, value) in [color.x(), color.y(), color.z()].iter().enumerate() {
+            let _ = self.splats[3 * index + channel].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f32::from_bits(bits) + value).to_bits()));
+        }
+    }
+
+    fn splat(&self, index: usize) -> Vec3 {
+        let channel = |channel: usize| f32::from_bits(self.splats[3 * index + channel].load(Ordering::Relaxed));
+        Vec3::new(channel(0), channel(1), channel(2))
+    }
+
+    fn set_splat(&mut self, index: usize, color: Vec3) {
+        for (channel, value) in [color.x(), color.y(), color.z()].iter().enumerate() {
+            *self.splats[3 * index + channel].get_mut() = value.to_bits();
+        }
+    }
+
+    //Copy of the region with its lower left corner at (x, y), in the same coordinates as pixels
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Film {
+        let mut film = Film::new(width, height).with_exposure(self.exposure);
+        for j in 0..height {
+            for i in 0..width {
+                let from = self.index(x + i, y + j);
+                let to = film.index(i, j);
+                film.pixels[to] = self.pixels[from];
+                film.set_splat(to, self.splat(from));
+            }
+        }
+        film
+    }
+
+    //Scales pixels and splats by falloff at the film position (s, t) of the pixel centers
+    pub fn vignette<F: Fn(f32, f32) -> f32>(&mut self, falloff: F) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let weight = falloff((x as f32 + 0.5) / self.width as f32, (y as f32 + 0.5) / self.height as f32);
+                let index = self.index(x, y);
+                self.pixels[index] = weight * self.pixels[index];
+                let splat = self.splat(index);
+                self.set_splat(index, weight * splat);
+            }
+        }
+    }
+
+    //Splats are summed over all samples, splat_scale normalizes them to the per pixel average
+    pub fn write_png(&self, filename: &str, splat_scale: f32) {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+
+        for (index, pixel) in self.pixels.iter().enumerate() {
+            let mut color = self.exposure * (*pixel + splat_scale * self.splat(index));
+
+            //Do gamma correction
+            color = color.clamp(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+            color = Vec3::new(color.x().sqrt(), color.y().sqrt(), color.z().sqrt());
+
+            data.push((255.99*color.x()) as u8);
+            data.push((255.99*color.y()) as u8);
+            data.push((255.99*color.z()) as u8);
+            data.push(255);
+        }
+
+        let path = Path::new(filename);
+        let file = File::create(path).unwrap();
+        let w = &mut BufWriter::new(file);
+
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+
+        writer.write_image_data(&data).unwrap();
+    }
+}
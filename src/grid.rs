@@ -0,0 +1,473 @@
+use vec3::Vec3;
+use ray::Ray;
+use aabb::AABB;
+use hitable::{Hit, Hitable};
+use material::Interface;
+use texture::TexCoord;
+use medium::{Medium, DensityField, MediumInterface};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+//Voxel density grids for smoke, clouds and fire, loaded from one of two formats.
+//
+//Header format: a text file of "key values" lines, # starts a comment
+//    resolution 64 32 64
+//    channels density temperature
+//    format f32
+//    data smoke.raw
+//The data file is looked up relative to the header and holds the voxels with x varying fastest,
+//then y, then z, channels interleaved per voxel. format is f32 (little endian, the default) or
+//u8 (mapped to [0, 1]).
+//
+//Dense text format: the keyword dense, the resolution and the channel names, followed by every
+//voxel value in the same order as above, all separated by whitespace
+//    dense 2 2 2 density
+//    0 0.5 0.5 1
+//    0 0.5 0.5 1
+//
+//The density channel is required. An optional second channel is either emission, radiance per
+//unit of absorption, or temperature in Kelvin which is converted to blackbody emission.
+
+const BRICK_SIZE: usize = 8;
+
+//Resolution, optional emission channel and the interleaved voxel values of a parsed file
+type GridData = ([usize; 3], Option<EmissionChannel>, Vec<f32>);
+
+#[derive(Clone, Copy, PartialEq)]
+enum EmissionChannel {
+    Emission,
+    Temperature
+}
+
+//Maps the unit cube of grid space to world space: scale, then rotation about y, then translation
+#[derive(Clone, Copy)]
+pub struct GridTransform {
+    translation: Vec3,
+    scale: Vec3,
+    sin_theta: f32,
+    cos_theta: f32
+}
+
+//Values are sampled at voxel centers and trilinearly interpolated, each brick of voxels stores
+//the largest density its interpolation can reach to bound tracking locally
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    density: Vec<f32>,
+    emission: Option<(EmissionChannel, Vec<f32>)>,
+    emission_scale: f32,
+    max_density: f32,
+    bricks: [usize; 3],
+    brick_max: Vec<f32>,
+    transform: GridTransform
+}
+
+//Box around a density grid, its interior is filled with a medium
+pub struct GridVolume {
+    transform: GridTransform,
+    interface: MediumInterface
+}
+
+impl GridTransform {
+    pub fn new(translation: Vec3, scale: Vec3, rotation_y: f32) -> GridTransform {
+        let radians = rotation_y.to_radians();
+        GridTransform {
+            translation,
+            scale,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos()
+        }
+    }
+
+    fn point_to_world(&self, p: Vec3) -> Vec3 {
+        let scaled = p * self.scale;
+        let x = self.cos_theta * scaled.x() + self.sin_theta * scaled.z();
+        let z = -self.sin_theta * scaled.x() + self.cos_theta * scaled.z();
+        Vec3::new(x, scaled.y(), z) + self.translation
+    }
+
+    fn vector_to_local(&self, v: Vec3) -> Vec3 {
+        let x = self.cos_theta * v.x() - self.sin_theta * v.z();
+        let z = self.sin_theta * v.x() + self.cos_theta * v.z();
+        Vec3::new(x / self.scale.x(), v.y() / self.scale.y(), z / self.scale.z())
+    }
+
+    fn point_to_local(&self, p: Vec3) -> Vec3 {
+        self.vector_to_local(p - self.translation)
+    }
+
+    //Rays keep their parametrization, so distances along them are the same in both spaces
+    fn ray_to_local(&self, r: &Ray) -> Ray {
+        Ray::new(self.point_to_local(r.origin()), self.vector_to_local(r.direction()))
+    }
+
+    //Normals transform with the inverse transpose
+    fn normal_to_world(&self, n: Vec3) -> Vec3 {
+        let scaled = Vec3::new(n.x() / self.scale.x(), n.y() / self.scale.y(), n.z() / self.scale.z());
+        let x = self.cos_theta * scaled.x() + self.sin_theta * scaled.z();
+        let z = -self.sin_theta * scaled.x() + self.cos_theta * scaled.z();
+        Vec3::unit_vector(Vec3::new(x, scaled.y(), z))
+    }
+
+    pub fn bounds(&self) -> AABB {
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
+        for corner in 0..8 {
+            let local = Vec3::new((corner & 1) as f32, ((corner >> 1) & 1) as f32, ((corner >> 2) & 1) as f32);
+            let p = self.point_to_world(local);
+            min = Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+            max = Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+        }
+        AABB::new(min, max)
+    }
+}
+
+fn unit_cube() -> AABB {
+    AABB::new(Vec3::zero_vector(), Vec3::new(1.0, 1.0, 1.0))
+}
+
+//Planck's law for a wavelength in nanometers
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    let c = 299_792_458.0;
+    let h = 6.626_070_15e-34;
+    let kb = 1.380_649e-23;
+    let l = wavelength * 1e-9;
+    (2.0 * h * c * c) / (l.powi(5) * ((h * c / (l * kb * temperature)).exp() - 1.0))
+}
+
+//Blackbody color sampled at representative red, green and blue wavelengths, normalized to a
+//maximum of one and scaled with the Stefan-Boltzmann law relative to 1000K
+fn blackbody(temperature: f32) -> Vec3 {
+    if temperature <= 0.0 {
+        return Vec3::zero_vector();
+    }
+    let t = f64::from(temperature);
+    let rgb = Vec3::new(planck(610.0, t) as f32, planck(550.0, t) as f32, planck(465.0, t) as f32);
+    let max = rgb.max_component();
+    if max <= 0.0 || !max.is_finite() {
+        return Vec3::zero_vector();
+    }
+    (temperature / 1000.0).powi(4) * rgb / max
+}
+
+fn parse_error(path: &Path, message: &str) -> String {
+    format!("{}: {}", path.display(), message)
+}
+
+fn parse_resolution(values: &[&str], path: &Path) -> Result<[usize; 3], String> {
+    if values.len() < 3 {
+        return Err(parse_error(path, "resolution needs three values"));
+    }
+    let mut resolution = [0; 3];
+    for (axis, value) in values.iter().take(3).enumerate() {
+        resolution[axis] = value.parse::<usize>().map_err(|_| parse_error(path, "invalid resolution"))?;
+        if resolution[axis] == 0 {
+            return Err(parse_error(path, "resolution must be positive"));
+        }
+    }
+    Ok(resolution)
+}
+
+fn parse_channels(names: &[&str], path: &Path) -> Result<Option<EmissionChannel>, String> {
+    match names {
+        ["density"] => Ok(None),
+        ["density", "emission"] => Ok(Some(EmissionChannel::Emission)),
+        ["density", "temperature"] => Ok(Some(EmissionChannel::Temperature)),
+        _ => Err(parse_error(path, "channels must be density, optionally followed by emission or temperature"))
+    }
+}
+
+impl DensityGrid {
+    //Loads either format, dense text files are recognized by their first keyword
+    pub fn load(path: &Path, transform: GridTransform, emission_scale: f32) -> Result<DensityGrid, String> {
+        let text = fs::read_to_string(path).map_err(|e| parse_error(path, &e.to_string()))?;
+        let (resolution, channel, values) = if text.trim_start().starts_with("dense") {
+            DensityGrid::parse_dense(&text, path)?
+        } else {
+            DensityGrid::parse_header(&text, path)?
+        };
+        Ok(DensityGrid::new(resolution, channel, values, transform, emission_scale))
+    }
+
+    fn parse_dense(text: &str, path: &Path) -> Result<GridData, String> {
+        let mut tokens = text.split_whitespace().skip(1).peekable();
+        let dims: Vec<&str> = tokens.by_ref().take(3).collect();
+        let resolution = parse_resolution(&dims, path)?;
+
+        let mut names = Vec::new();
+        while let Some(&name) = tokens.peek() {
+            if name.parse::<f32>().is_ok() {
+                break;
+            }
+            names.push(name);
+            tokens.next();
+        }
+        let channel = parse_channels(&names, path)?;
+
+        let values = tokens.map(|value| value.parse::<f32>().map_err(|_| parse_error(path, "invalid voxel value")))
+                           .collect::<Result<Vec<f32>, String>>()?;
+        Ok((resolution, channel, values))
+    }
+
+    fn parse_header(text: &str, path: &Path) -> Result<GridData, String> {
+        let mut resolution = None;
+        let mut channel = None;
+        let mut format = "f32";
+        let mut data = None;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.split_first() {
+                Some((&"resolution", values)) => resolution = Some(parse_resolution(values, path)?),
+                Some((&"channels", names)) => channel = Some(parse_channels(names, path)?),
+                Some((&"format", [value])) => format = value,
+                Some((&"data", [file])) => data = Some(*file),
+                Some(_) => return Err(parse_error(path, &format!("unknown header line \"{}\"", line.trim()))),
+                None => {}
+            }
+        }
+
+        let resolution = resolution.ok_or_else(|| parse_error(path, "missing resolution"))?;
+        let channel = channel.unwrap_or(None);
+        let data = data.ok_or_else(|| parse_error(path, "missing data file"))?;
+        let data_path = path.parent().unwrap_or_else(|| Path::new("")).join(data);
+        let bytes = fs::read(&data_path).map_err(|e| parse_error(&data_path, &e.to_string()))?;
+
+        let values = match format {
+            "f32" => bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+            "u8" => bytes.iter().map(|&b| f32::from(b) / 255.0).collect(),
+            _ => return Err(parse_error(path, "format must be f32 or u8"))
+        };
+        Ok((resolution, channel, values))
+    }
+
+    //Missing voxels are treated as empty, extra values are ignored
+    fn new(resolution: [usize; 3], channel: Option<EmissionChannel>, values: Vec<f32>, transform: GridTransform, emission_scale: f32) -> DensityGrid {
+        let voxel_count = resolution[0] * resolution[1] * resolution[2];
+        let stride = if channel.is_some() { 2 } else { 1 };
+        let value = |voxel: usize, offset: usize| values.get(voxel * stride + offset).cloned().unwrap_or(0.0).max(0.0);
+        let density: Vec<f32> = (0..voxel_count).map(|voxel| value(voxel, 0)).collect();
+        let emission = channel.map(|channel| (channel, (0..voxel_count).map(|voxel| value(voxel, 1)).collect()));
+
+        let bricks = [
+            resolution[0].div_ceil(BRICK_SIZE),
+            resolution[1].div_ceil(BRICK_SIZE),
+            resolution[2].div_ceil(BRICK_SIZE)
+        ];
+        let mut brick_max = vec![0.0; bricks[0] * bricks[1] * bricks[2]];
+        for bz in 0..bricks[2] {
+            for by in 0..bricks[1] {
+                for bx in 0..bricks[0] {
+                    //Interpolation inside a brick reaches one voxel beyond it on every side
+                    let range = |b: usize, n: usize| (b * BRICK_SIZE).saturating_sub(1)..((b + 1) * BRICK_SIZE + 1).min(n);
+                    let mut max: f32 = 0.0;
+                    for z in range(bz, resolution[2]) {
+                        for y in range(by, resolution[1]) {
+                            for x in range(bx, resolution[0]) {
+                                max = max.max(density[(z * resolution[1] + y) * resolution[0] + x]);
+                            }
+                        }
+                    }
+                    brick_max[(bz * bricks[1] + by) * bricks[0] + bx] = max;
+                }
+            }
+        }
+        let max_density = brick_max.iter().cloned().fold(0.0, f32::max);
+
+        DensityGrid {
+            resolution,
+            density,
+            emission,
+            emission_scale,
+            max_density,
+            bricks,
+            brick_max,
+            transform
+        }
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    pub fn with_transform(self, transform: GridTransform) -> DensityGrid {
+        DensityGrid {
+            transform,
+            ..self
+        }
+    }
+
+    fn voxel(&self, values: &[f32], x: isize, y: isize, z: isize) -> f32 {
+        let clamp = |i: isize, n: usize| i.max(0).min(n as isize - 1) as usize;
+        let x = clamp(x, self.resolution[0]);
+        let y = clamp(y, self.resolution[1]);
+        let z = clamp(z, self.resolution[2]);
+        values[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+
+    fn lookup(&self, values: &[f32], local: Vec3) -> f32 {
+        let gx = local.x() * self.resolution[0] as f32 - 0.5;
+        let gy = local.y() * self.resolution[1] as f32 - 0.5;
+        let gz = local.z() * self.resolution[2] as f32 - 0.5;
+        let (x, y, z) = (gx.floor(), gy.floor(), gz.floor());
+        let (fx, fy, fz) = (gx - x, gy - y, gz - z);
+        let (x, y, z) = (x as isize, y as isize, z as isize);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(self.voxel(values, x, y, z), self.voxel(values, x + 1, y, z), fx);
+        let c10 = lerp(self.voxel(values, x, y + 1, z), self.voxel(values, x + 1, y + 1, z), fx);
+        let c01 = lerp(self.voxel(values, x, y, z + 1), self.voxel(values, x + 1, y, z + 1), fx);
+        let c11 = lerp(self.voxel(values, x, y + 1, z + 1), self.voxel(values, x + 1, y + 1, z + 1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    fn to_local(&self, p: Vec3) -> Option<Vec3> {
+        let local = self.transform.point_to_local(p);
+        let inside = |v: f32| (0.0..=1.0).contains(&v);
+        if inside(local.x()) && inside(local.y()) && inside(local.z()) {
+            Some(local)
+        } else {
+            None
+        }
+    }
+}
+
+impl DensityField for DensityGrid {
+    fn density(&self, p: Vec3) -> f32 {
+        match self.to_local(p) {
+            Some(local) => self.lookup(&self.density, local),
+            None => 0.0
+        }
+    }
+
+    fn max_density(&self) -> f32 {
+        self.max_density
+    }
+
+    fn bounds(&self) -> AABB {
+        self.transform.bounds()
+    }
+
+    fn emission(&self, p: Vec3) -> Vec3 {
+        let (channel, values) = match (&self.emission, self.to_local(p)) {
+            (Some((channel, values)), Some(_)) => (channel, values),
+            _ => return Vec3::zero_vector()
+        };
+        let value = self.lookup(values, self.transform.point_to_local(p));
+        match channel {
+            EmissionChannel::Emission => self.emission_scale * Vec3::new(value, value, value),
+            EmissionChannel::Temperature => self.emission_scale * blackbody(value)
+        }
+    }
+
+    //Walks the bricks pierced by the ray with a 3D DDA
+    fn majorants(&self, r: &Ray, t_max: f32, visit: &mut dyn FnMut(f32, f32, f32) -> bool) {
+        let local = self.transform.ray_to_local(r);
+        let (t_start, t_end) = match unit_cube().intersect(&local, 0.0, t_max) {
+            Some(range) => range,
+            None => return
+        };
+
+        let origin = [local.origin().x(), local.origin().y(), local.origin().z()];
+        let direction = [local.direction().x(), local.direction().y(), local.direction().z()];
+        let mut cell = [0isize; 3];
+        let mut step = [0isize; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            //Bricks per unit of grid space
+            let scale = self.resolution[axis] as f32 / BRICK_SIZE as f32;
+            let position = (origin[axis] + t_start * direction[axis]) * scale;
+            cell[axis] = (position.floor() as isize).max(0).min(self.bricks[axis] as isize - 1);
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                t_next[axis] = ((cell[axis] + 1) as f32 / scale - origin[axis]) / direction[axis];
+                t_delta[axis] = 1.0 / (scale * direction[axis]);
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                t_next[axis] = (cell[axis] as f32 / scale - origin[axis]) / direction[axis];
+                t_delta[axis] = -1.0 / (scale * direction[axis]);
+            }
+        }
+
+        let mut t = t_start;
+        loop {
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+                0
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+            let t_exit = t_next[axis].min(t_end);
+            let index = (cell[2] as usize * self.bricks[1] + cell[1] as usize) * self.bricks[0] + cell[0] as usize;
+            if t_exit > t && !visit(t, t_exit, self.brick_max[index]) {
+                return;
+            }
+            if t_exit >= t_end {
+                return;
+            }
+            t = t_exit;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.bricks[axis] as isize {
+                return;
+            }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+impl GridVolume {
+    //outside is the medium surrounding the volume, where rays leaving it continue
+    pub fn new(transform: GridTransform, medium: Arc<dyn Medium + Send + Sync>, outside: Option<Arc<dyn Medium + Send + Sync>>) -> GridVolume {
+        GridVolume {
+            transform,
+            interface: MediumInterface::new(Some(medium), outside)
+        }
+    }
+}
+
+impl Hitable for GridVolume {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
+        let local = self.transform.ray_to_local(r);
+        let (t_enter, t_exit) = unit_cube().intersect(&local, f32::NEG_INFINITY, f32::INFINITY)?;
+        let (t, front_face) = if t_enter > t_min && t_enter < t_max {
+            (t_enter, true)
+        } else if t_exit > t_min && t_exit < t_max {
+            (t_exit, false)
+        } else {
+            return None;
+        };
+
+        //Outward normal of the face closest to the local hit point
+        let p = local.point_at_parameter(t);
+        let distances = [p.x().abs(), (p.x() - 1.0).abs(), p.y().abs(), (p.y() - 1.0).abs(), p.z().abs(), (p.z() - 1.0).abs()];
+        let mut face = 0;
+        for (i, distance) in distances.iter().enumerate() {
+            if *distance < distances[face] {
+                face = i;
+            }
+        }
+        let sign = if face % 2 == 0 { -1.0 } else { 1.0 };
+        let local_normal = match face / 2 {
+            0 => Vec3::new(sign, 0.0, 0.0),
+            1 => Vec3::new(0.0, sign, 0.0),
+            _ => Vec3::new(0.0, 0.0, sign)
+        };
+
+        Some(Hit {
+            t,
+            p: r.point_at_parameter(t),
+            normal: self.transform.normal_to_world(local_normal),
+            front_face,
+            material: &Interface,
+            medium_interface: Some(&self.interface),
+            uv: TexCoord::new(0.0, 0.0)
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.transform.bounds()
+    }
+}
@@ -0,0 +1,253 @@
+use vec3::Vec3;
+use std::fs;
+use std::path::Path;
+
+extern crate exr;
+
+//Decoders of floating point images, returning width, height and linear pixels row by row from
+//the top
+
+pub type Pixels = (usize, usize, Vec<Vec3>);
+
+fn error(path: &Path, e: &dyn std::fmt::Display) -> String {
+    format!("{}: {}", path.display(), e)
+}
+
+//Radiance RGBE (.hdr, .pic), flat or with run length encoded scanlines
+pub fn load_radiance(path: &Path) -> Result<Pixels, String> {
+    let data = fs::read(path).map_err(|e| error(path, &e))?;
+    //The header ends with an empty line, followed by the resolution line
+    let mut position = 0;
+    let mut line = || -> Option<String> {
+        let end = position + data[position..].iter().position(|&byte| byte == b'\n')?;
+        let text = String::from_utf8_lossy(&data[position..end]).trim().to_string();
+        position = end + 1;
+        Some(text)
+    };
+    let signature = line().ok_or_else(|| error(path, &"empty file"))?;
+    if !signature.starts_with("#?") {
+        return Err(error(path, &"not a Radiance file"));
+    }
+    loop {
+        match line() {
+            Some(ref text) if text.is_empty() => break,
+            Some(ref text) if text.starts_with("FORMAT=") && text != "FORMAT=32-bit_rle_rgbe" => return Err(error(path, &"unsupported format, only rgbe")),
+            Some(_) => {},
+            None => return Err(error(path, &"truncated header"))
+        }
+    }
+    let resolution = line().ok_or_else(|| error(path, &"missing resolution"))?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match fields[..] {
+        ["-Y", height, "+X", width] => (false, height, width),
+        ["+Y", height, "+X", width] => (true, height, width),
+        _ => return Err(error(path, &format!("unsupported orientation {}", resolution)))
+    };
+    let height: usize = height.parse().map_err(|e| error(path, &e))?;
+    let width: usize = width.parse().map_err(|e| error(path, &e))?;
+    if width == 0 || height == 0 {
+        return Err(error(path, &"empty image"));
+    }
+
+    let mut rgbe = vec![0u8; width * height * 4];
+    for row in rgbe.chunks_mut(width * 4) {
+        let rle = (8..32768).contains(&width) && data.len() >= position + 4 && data[position] == 2 && data[position + 1] == 2
+            && ((data[position + 2] as usize) << 8 | data[position + 3] as usize) == width;
+        if !rle {
+            let bytes = data.get(position..position + width * 4).ok_or_else(|| error(path, &"truncated pixels"))?;
+            row.copy_from_slice(bytes);
+            position += width * 4;
+            continue;
+        }
+        //Each of the four components is stored separately as runs and literals
+        position += 4;
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = *data.get(position).ok_or_else(|| error(path, &"truncated scanline"))? as usize;
+                position += 1;
+                if count > 128 {
+                    let value = *data.get(position).ok_or_else(|| error(path, &"truncated scanline"))?;
+                    position += 1;
+                    for _ in 0..(count - 128).min(width - x) {
+                        row[x * 4 + component] = value;
+                        x += 1;
+                    }
+                } else {
+                    if count == 0 || x + count > width {
+                        return Err(error(path, &"corrupt scanline"));
+                    }
+                    let values = data.get(position..position + count).ok_or_else(|| error(path, &"truncated scanline"))?;
+                    for value in values {
+                        row[x * 4 + component] = *value;
+                        x += 1;
+                    }
+                    position += count;
+                }
+            }
+        }
+    }
+
+    let mut pixels: Vec<Vec3> = rgbe.chunks(4).map(|texel| {
+        if texel[3] == 0 {
+            return Vec3::zero_vector();
+        }
+        let scale = 2f32.powi(texel[3] as i32 - 136);
+        Vec3::new((texel[0] as f32 + 0.5) * scale, (texel[1] as f32 + 0.5) * scale, (texel[2] as f32 + 0.5) * scale)
+    }).collect();
+    if flip {
+        flip_rows(&mut pixels, width);
+    }
+    Ok((width, height, pixels))
+}
+
+//Portable float map, color (PF) or gray (Pf). A negative scale marks little endian data, rows
+//are stored from the bottom.
+pub fn load_pfm(path: &Path) -> Result<Pixels, String> {
+    let data = fs::read(path).map_err(|e| error(path, &e))?;
+    //Three whitespace separated header tokens after the magic, then a single whitespace byte
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while tokens.len() < 4 {
+        while data.get(position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            position += 1;
+        }
+        let start = position;
+        while data.get(position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+            position += 1;
+        }
+        if start == position {
+            return Err(error(path, &"truncated header"));
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..position]).to_string());
+    }
+    position += 1;
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(error(path, &"not a PFM file"))
+    };
+    let width: usize = tokens[1].parse().map_err(|e| error(path, &e))?;
+    let height: usize = tokens[2].parse().map_err(|e| error(path, &e))?;
+    let scale: f32 = tokens[3].parse().map_err(|e| error(path, &e))?;
+    if width == 0 || height == 0 {
+        return Err(error(path, &"empty image"));
+    }
+    let bytes = data.get(position..position + width * height * channels * 4).ok_or_else(|| error(path, &"truncated pixels"))?;
+    let floats: Vec<f32> = bytes.chunks(4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+    }).collect();
+    let mut pixels: Vec<Vec3> = floats.chunks(channels).map(|texel| if channels == 1 {
+        Vec3::new(texel[0], texel[0], texel[0])
+    } else {
+        Vec3::new(texel[0], texel[1], texel[2])
+    }).collect();
+    flip_rows(&mut pixels, width);
+    Ok((width, height, pixels))
+}
+
+//OpenEXR, the first layer with rgb channels at its largest resolution level
+pub fn load_exr(path: &Path) -> Result<Pixels, String> {
+    use self::exr::prelude::*;
+    let image = read_first_rgba_layer_from_file(path,
+        |resolution: Vec2<usize>, _: &RgbaChannels| (resolution.width(), resolution.height(), vec![Vec3::zero_vector(); resolution.width() * resolution.height()]),
+        |pixels: &mut Pixels, position: Vec2<usize>, (r, g, b, _): (f32, f32, f32, f32)| {
+            let width = pixels.0;
+            pixels.2[position.y() * width + position.x()] = Vec3::new(r, g, b);
+        }).map_err(|e| error(path, &e))?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+fn flip_rows(pixels: &mut [Vec3], width: usize) {
+    let height = pixels.len() / width;
+    for y in 0..height / 2 {
+        let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+        top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn try_load(name: &str, data: &[u8], loader: fn(&Path) -> Result<Pixels, String>) -> Result<Pixels, String> {
+        let path = std::env::temp_dir().join(format!("hdr-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        let pixels = loader(&path);
+        fs::remove_file(&path).unwrap();
+        pixels
+    }
+
+    fn load(name: &str, data: &[u8], loader: fn(&Path) -> Result<Pixels, String>) -> Pixels {
+        try_load(name, data, loader).unwrap()
+    }
+
+    fn radiance(resolution: &str, pixels: &[u8]) -> Vec<u8> {
+        let mut data = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    fn rgbe(texel: [u8; 4]) -> Vec3 {
+        let scale = 2f32.powi(texel[3] as i32 - 136);
+        Vec3::new((texel[0] as f32 + 0.5) * scale, (texel[1] as f32 + 0.5) * scale, (texel[2] as f32 + 0.5) * scale)
+    }
+
+    fn pfm(header: &str, floats: &[f32], little_endian: bool) -> Vec<u8> {
+        let mut data = header.as_bytes().to_vec();
+        for value in floats {
+            data.extend_from_slice(&if little_endian { value.to_le_bytes() } else { value.to_be_bytes() });
+        }
+        data
+    }
+
+    #[test]
+    fn radiance_flat() {
+        let (width, height, pixels) = load("flat.hdr", &radiance("-Y 1 +X 2", &[128, 64, 32, 129, 0, 0, 0, 0]), load_radiance);
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, vec![rgbe([128, 64, 32, 129]), Vec3::zero_vector()]);
+    }
+
+    #[test]
+    fn radiance_run_length_scanline() {
+        //Eight pixels, red as a run, green as literals, blue and exponent as runs
+        let mut scanline = vec![2, 2, 0, 8];
+        scanline.extend_from_slice(&[128 + 8, 200]);
+        scanline.extend_from_slice(&[8, 1, 2, 3, 4, 5, 6, 7, 8]);
+        scanline.extend_from_slice(&[128 + 4, 10, 128 + 4, 20]);
+        scanline.extend_from_slice(&[128 + 8, 130]);
+        let (width, height, pixels) = load("rle.hdr", &radiance("-Y 1 +X 8", &scanline), load_radiance);
+        assert_eq!((width, height), (8, 1));
+        let expected: Vec<Vec3> = (0..8).map(|x| rgbe([200, x as u8 + 1, if x < 4 { 10 } else { 20 }, 130])).collect();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn radiance_bottom_up_rows_are_flipped() {
+        let (_, _, pixels) = load("flip.hdr", &radiance("+Y 2 +X 1", &[1, 1, 1, 128, 2, 2, 2, 128]), load_radiance);
+        assert_eq!(pixels, vec![rgbe([2, 2, 2, 128]), rgbe([1, 1, 1, 128])]);
+    }
+
+    #[test]
+    fn pfm_little_endian() {
+        //Rows are stored from the bottom
+        let (width, height, pixels) = load("little.pfm", &pfm("PF\n1 2\n-1.0\n", &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], true), load_pfm);
+        assert_eq!((width, height), (1, 2));
+        assert_eq!(pixels, vec![Vec3::new(4.0, 5.0, 6.0), Vec3::new(1.0, 2.0, 3.0)]);
+    }
+
+    #[test]
+    fn pfm_big_endian_gray() {
+        let (width, height, pixels) = load("big.pfm", &pfm("Pf\n2 1\n1.0\n", &[0.25, 8.0], false), load_pfm);
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, vec![Vec3::new(0.25, 0.25, 0.25), Vec3::new(8.0, 8.0, 8.0)]);
+    }
+
+    #[test]
+    fn empty_images_are_errors() {
+        assert!(try_load("empty.hdr", &radiance("-Y 0 +X 0", &[]), load_radiance).is_err());
+        assert!(try_load("empty.pfm", b"PF\n0 0\n-1\n", load_pfm).is_err());
+        assert!(try_load("empty_row.pfm", b"Pf\n0 2\n-1\n", load_pfm).is_err());
+    }
+}
@@ -2,10 +2,13 @@ use aabb::AABB;
 use aabb::surrounding_bbox;
 use texture::Texture;
 use vec3::Vec3;
-use material::Material;
+use texture::TexCoord;
+use material::{Material, ScatterRecord};
 use ray::Ray;
 use material::Isotropic;
 use std::cmp::Ordering;
+use sampler;
+use medium::MediumInterface;
 
 extern crate rand;
 
@@ -13,12 +16,52 @@ pub struct Hit<'a> {
     pub t: f32,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: &'a Box<(dyn Material + Sync)>
+    //The ray arrived from the side the geometric normal of the primitive points to
+    pub front_face: bool,
+    pub material: &'a (dyn Material + Sync),
+    pub medium_interface: Option<&'a MediumInterface>,
+    pub uv: TexCoord
+}
+
+impl<'a> Hit<'a> {
+    //Scatters r at the hit, dielectrics refract between their interior and vacuum on the side
+    //the ray arrives from. Only the path integrator tracks nested dielectrics.
+    pub fn scatter(&self, r: &Ray) -> Option<ScatterRecord> {
+        match self.material.interior() {
+            Some(interior) => {
+                let eta = if self.front_face { interior.ior } else { 1.0 / interior.ior };
+                self.material.scatter_between(r, self.t, self.p, self.normal, self.uv, eta)
+            },
+            None => self.material.scatter(r, self.t, self.p, self.normal, self.uv)
+        }
+    }
+
+    //Whether a ray leaving the hit point along direction ends up on the outside of the primitive
+    pub fn leaves_outside(&self, r: &Ray, direction: Vec3) -> bool {
+        let reflected = direction.dot(self.normal) * r.direction().dot(self.normal) < 0.0;
+        reflected == self.front_face
+    }
+}
+
+//Point sampled uniformly by area on the surface of a hitable
+pub struct SurfaceSample<'a> {
+    pub p: Vec3,
+    pub normal: Vec3,
+    pub material: &'a (dyn Material + Sync),
+    pub uv: TexCoord
 }
 
 pub trait Hitable {
-    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit>;
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>>;
     fn bounding_box(&self) -> AABB;
+    fn area(&self) -> f32 {
+        0.0
+    }
+    fn sample_surface(&self) -> Option<SurfaceSample<'_>> {
+        None
+    }
+    //Collects every primitive with an emissive material
+    fn emitters<'a>(&'a self, _lights: &mut Vec<&'a (dyn Hitable + Sync)>) {}
 }
 
 pub struct ConstantMedium {
@@ -27,6 +70,13 @@ pub struct ConstantMedium {
     material: Box<dyn Material + Sync>
 }
 
+//Attaches participating media to the inside and outside of a closed shape. Give the shape the
+//Interface material for a boundary that only separates media.
+pub struct MediumBoundary {
+    shape: Box<dyn Hitable + Sync>,
+    interface: MediumInterface
+}
+
 pub struct BvhNode {
     left: Box<dyn Hitable + Sync>,
     right: Option<Box<dyn Hitable + Sync>>,
@@ -44,41 +94,40 @@ impl ConstantMedium {
 }
 
 impl Hitable for ConstantMedium {
-    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
-        let hit1 = self.boundary.hit(-1000.0, 1000.0, r);
-        if hit1.is_some() {
-            let mut hit1 = hit1.unwrap();
-            let hit2 = self.boundary.hit(hit1.t + 0.0001, 1000.0, r);
-
-            if hit2.is_some() {
-                let mut hit2 = hit2.unwrap();
-                if hit1.t < t_min {
-                    hit1.t = t_min;
-                }
-                if hit2.t > t_max {
-                    hit2.t = t_max;
-                }
-                if hit1.t >= hit2.t {
-                    return None;
-                }
-                if hit1.t < 0.0 {
-                    hit1.t = 0.0;
-                }
+    //Walks every boundary crossing along the ray, the boundary does not need to be convex.
+    //Crossings alternate between entering and leaving, starting from outside at t = -infinity.
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
+        let length = r.direction().length();
+        let mut crossing = f32::NEG_INFINITY;
+        let mut inside = false;
+
+        while crossing < t_max {
+            let next = match self.boundary.hit(crossing + 0.0001 / length, f32::INFINITY, r) {
+                Some(hit) => hit.t,
+                None => break
+            };
 
-                let distance_inside_boundary = (hit2.t - hit1.t) * r.direction().length();
-                let hit_distance = (-1.0/self.density) * rand::random::<f32>().ln();
+            if inside && next > t_min {
+                let start = crossing.max(t_min);
+                let end = next.min(t_max);
+                let distance_inside_boundary = (end - start) * length;
+                let hit_distance = (-1.0/self.density) * sampler::random().ln();
 
-                //println!("Distance inside boundary: {}, Hit distance: {}", distance_inside_boundary, hit_distance);
                 if hit_distance < distance_inside_boundary {
-                    let t = hit1.t + (hit_distance / r.direction().length());
+                    let t = start + (hit_distance / length);
                     return Some(Hit {
                         t,
                         p: r.point_at_parameter(t),
-                        normal: Vec3::new(1.0, 0.0, 0.0), //arbitrary vector
-                        material: &self.material
+                        normal: Vec3::zero_vector(), //scattering inside a volume has no surface normal
+                        front_face: true,
+                        material: &*self.material,
+                        medium_interface: None,
+                        uv: TexCoord::new(0.0, 0.0)
                     });
                 }
             }
+            crossing = next;
+            inside = !inside;
         }
         None
     }
@@ -88,8 +137,41 @@ impl Hitable for ConstantMedium {
     }
 }
 
+impl MediumBoundary {
+    pub fn new(shape: Box<dyn Hitable + Sync>, interface: MediumInterface) -> MediumBoundary {
+        MediumBoundary {
+            shape,
+            interface
+        }
+    }
+}
+
+impl Hitable for MediumBoundary {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
+        let mut hit = self.shape.hit(t_min, t_max, r)?;
+        hit.medium_interface = Some(&self.interface);
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
+
+    fn area(&self) -> f32 {
+        self.shape.area()
+    }
+
+    fn sample_surface(&self) -> Option<SurfaceSample<'_>> {
+        self.shape.sample_surface()
+    }
+
+    fn emitters<'a>(&'a self, lights: &mut Vec<&'a (dyn Hitable + Sync)>) {
+        self.shape.emitters(lights);
+    }
+}
+
 impl Hitable for BvhNode {
-    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
         let bbox_hit = self.bbox.hit(r, t_min, t_max);
         if bbox_hit {
             let left_hit = self.left.hit(t_min, t_max, r);
@@ -122,6 +204,12 @@ impl Hitable for BvhNode {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+    fn emitters<'a>(&'a self, lights: &mut Vec<&'a (dyn Hitable + Sync)>) {
+        self.left.emitters(lights);
+        if let Some(ref right) = self.right {
+            right.emitters(lights);
+        }
+    }
 }
 
 impl BvhNode {
@@ -190,7 +278,7 @@ impl BvhNode {
             let left_bbox = left.bounding_box();
             let right_bbox = right.bounding_box();
             BvhNode {
-                left: left,
+                left,
                 right: Some(right),
                 bbox: surrounding_bbox(left_bbox, right_bbox)
             }
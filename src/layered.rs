@@ -0,0 +1,195 @@
+use vec3::Vec3;
+use ray::Ray;
+use texture::{Texture, TexCoord};
+use material::{Material, ScatterRecord};
+use microfacet::{Frame, TrowbridgeReitz, fresnel_dielectric};
+use microfacet;
+use sampler;
+
+//Blend of two materials by the first channel of a mask, 0 gives the first material and 1 the
+//second. Scattering picks one of them at random with the blend weight.
+pub struct MixMaterial {
+    first: Box<dyn Material + Sync>,
+    second: Box<dyn Material + Sync>,
+    mask: Box<dyn Texture + Sync>
+}
+
+//Smooth or rough dielectric coat over any base material. Light reaching the base is weighted by
+//the Fresnel transmittance into and out of the coat and by the absorption of the coat along both
+//directions. Directions are not bent by the coat and light reflected inside it is lost.
+pub struct LayeredMaterial {
+    base: Box<dyn Material + Sync>,
+    ior: f32,
+    distribution: TrowbridgeReitz,
+    //Transmittance of the coat at normal incidence, once through
+    color: Vec3
+}
+
+impl MixMaterial {
+    pub fn new(first: Box<dyn Material + Sync>, second: Box<dyn Material + Sync>, mask: Box<dyn Texture + Sync>) -> MixMaterial {
+        MixMaterial {
+            first,
+            second,
+            mask
+        }
+    }
+
+    fn amount(&self, point: Vec3, uv: TexCoord) -> f32 {
+        self.mask.filtered(&uv, &point).x().clamp(0.0, 1.0)
+    }
+}
+
+impl LayeredMaterial {
+    pub fn new(base: Box<dyn Material + Sync>, ior: f32, roughness: f32) -> LayeredMaterial {
+        LayeredMaterial {
+            base,
+            ior,
+            distribution: TrowbridgeReitz::from_roughness(roughness, 0.0),
+            color: Vec3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    //Tints the coat so that light keeps color after passing through it once at normal incidence
+    pub fn with_color(self, color: Vec3) -> LayeredMaterial {
+        LayeredMaterial {
+            color,
+            ..self
+        }
+    }
+
+    //The coat is two sided, the frame has wo above the surface
+    fn frame(wo: Vec3, normal: Vec3) -> Frame {
+        Frame::new(if wo.dot(normal) < 0.0 { -normal } else { normal })
+    }
+
+    //Fresnel transmittance into the coat times absorption on the way through it, for a local
+    //direction above the surface
+    fn transmittance(&self, w: Vec3) -> (f32, Vec3) {
+        let cos_theta = w.z().max(0.000_001);
+        let transmitted = 1.0 - fresnel_dielectric(cos_theta, self.ior);
+        //Path length grows with the angle of the direction refracted into the coat
+        let sin2 = (1.0 - cos_theta * cos_theta) / (self.ior * self.ior);
+        let cos_inside = (1.0 - sin2).max(0.000_001).sqrt();
+        let channel = |c: f32| c.max(0.000_001).powf(1.0 / cos_inside);
+        (transmitted, Vec3::new(channel(self.color.x()), channel(self.color.y()), channel(self.color.z())))
+    }
+
+    fn coat_eval(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if self.distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.eval_dielectric(wo, wi, self.ior).0
+    }
+
+    fn coat_pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if self.distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = Vec3::unit_vector(wo + wi);
+        self.distribution.pdf(wo, wh) / (4.0 * wo.dot(wh))
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r: &Ray, t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
+        if sampler::random() < self.amount(point, uv) {
+            self.second.scatter(r, t, point, normal, uv)
+        } else {
+            self.first.scatter(r, t, point, normal, uv)
+        }
+    }
+    fn emitted(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let amount = self.amount(*p, TexCoord::new(u, v));
+        (1.0 - amount) * self.first.emitted(u, v, p) + amount * self.second.emitted(u, v, p)
+    }
+    fn eval(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> Vec3 {
+        let amount = self.amount(point, uv);
+        (1.0 - amount) * self.first.eval(wo, wi, point, normal, uv) + amount * self.second.eval(wo, wi, point, normal, uv)
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> f32 {
+        let amount = self.amount(point, uv);
+        (1.0 - amount) * self.first.pdf(wo, wi, point, normal, uv) + amount * self.second.pdf(wo, wi, point, normal, uv)
+    }
+    fn is_emissive(&self) -> bool {
+        self.first.is_emissive() || self.second.is_emissive()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.first.is_dispersive() || self.second.is_dispersive()
+    }
+}
+
+impl Material for LayeredMaterial {
+    //The coat is chosen with its reflectance towards wo, which cancels the transmittance into it
+    fn scatter(&self, r: &Ray, t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
+        let wo = -Vec3::unit_vector(r.direction());
+        let frame = LayeredMaterial::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let coat_reflectance = fresnel_dielectric(wo_local.z(), self.ior);
+
+        if sampler::random() < coat_reflectance {
+            if self.distribution.is_smooth() {
+                return Some(ScatterRecord {
+                    attenuation: Vec3::new(1.0, 1.0, 1.0),
+                    scattered: Ray::new(point, frame.to_world(microfacet::reflect(wo_local, Vec3::new(0.0, 0.0, 1.0)))),
+                    is_specular: true
+                });
+            }
+            let wh = self.distribution.sample_wh(wo_local, sampler::random(), sampler::random());
+            let wi_local = microfacet::reflect(wo_local, wh);
+            if wi_local.z() <= 0.0 {
+                return None;
+            }
+            let weight = fresnel_dielectric(wo_local.dot(wh), self.ior) / coat_reflectance
+                * self.distribution.g(wo_local, wi_local) / self.distribution.g1(wo_local);
+            return Some(ScatterRecord {
+                attenuation: Vec3::new(weight, weight, weight),
+                scattered: Ray::new(point, frame.to_world(wi_local)),
+                is_specular: false
+            });
+        }
+
+        let scatter_rec = self.base.scatter(r, t, point, normal, uv)?;
+        let wi_local = frame.to_local(Vec3::unit_vector(scatter_rec.scattered.direction()));
+        let (_, absorption_in) = self.transmittance(wo_local);
+        //Transmitted by the base, the light crosses the coat only on the way in
+        if wi_local.z() <= 0.0 {
+            return Some(ScatterRecord {
+                attenuation: scatter_rec.attenuation * absorption_in,
+                ..scatter_rec
+            });
+        }
+        let (transmitted_out, absorption_out) = self.transmittance(wi_local);
+        Some(ScatterRecord {
+            attenuation: scatter_rec.attenuation * absorption_in * absorption_out * transmitted_out,
+            ..scatter_rec
+        })
+    }
+    fn emitted(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.base.emitted(u, v, p)
+    }
+    fn eval(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> Vec3 {
+        let frame = LayeredMaterial::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let wi_local = frame.to_local(wi);
+        let base = self.base.eval(wo, wi, point, normal, uv);
+        let (transmitted_in, absorption_in) = self.transmittance(wo_local);
+        if wi_local.z() <= 0.0 {
+            return base * absorption_in * transmitted_in;
+        }
+        let (transmitted_out, absorption_out) = self.transmittance(wi_local);
+        let coat = self.coat_eval(wo_local, wi_local);
+        Vec3::new(coat, coat, coat) + base * absorption_in * absorption_out * (transmitted_in * transmitted_out)
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> f32 {
+        let frame = LayeredMaterial::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let coat_reflectance = fresnel_dielectric(wo_local.z(), self.ior);
+        coat_reflectance * self.coat_pdf(wo_local, frame.to_local(wi)) + (1.0 - coat_reflectance) * self.base.pdf(wo, wi, point, normal, uv)
+    }
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
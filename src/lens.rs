@@ -0,0 +1,321 @@
+use vec3::Vec3;
+use ray::{Ray, RayDifferential};
+use camera::{Camera, CameraFrame, PhysicalSettings, pixel_spacing};
+use aperture::Aperture;
+use spectrum::Dispersion;
+use std::fs;
+use std::path::Path;
+
+//One spherical surface of a lens prescription, listed from the scene towards the sensor.
+//Lengths are in scene units.
+#[derive(Debug, Clone, Copy)]
+struct LensInterface {
+    //Positive when the center of curvature lies towards the sensor, zero for the aperture stop
+    radius: f32,
+    //Distance along the axis to the next interface, or to the sensor for the last one
+    thickness: f32,
+    //Glass between this interface and the next one
+    medium: Dispersion,
+    aperture_radius: f32
+}
+
+//Camera tracing rays from the sensor through the elements of a real lens, which brings its
+//distortion, vignetting, focus breathing and, rendering spectrally, chromatic aberration. Lens
+//space follows pbrt, the sensor lies in the plane z = 0 and the lens extends towards negative z.
+#[derive(Debug)]
+pub struct RealisticCamera {
+    frame: CameraFrame,
+    interfaces: Vec<LensInterface>,
+    sensor_width: f32,
+    sensor_height: f32,
+    //Radius on the rear element that rays reaching the scene pass within, and the fraction of
+    //it open as seen from the center of the sensor
+    pupil_radius: f32,
+    center_transmission: f32,
+    pixel_spacing: Option<(f32, f32)>
+}
+
+//Sphere of the given radius centered on the axis at z, returning the distance along direction
+//and the normal facing against it
+fn intersect_element(radius: f32, z: f32, origin: Vec3, direction: Vec3) -> Option<(f32, Vec3)> {
+    let o = origin - Vec3::new(0.0, 0.0, z);
+    let a = direction.squared_length();
+    let b = 2.0 * direction.dot(o);
+    let c = o.squared_length() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let q = if b < 0.0 { -0.5 * (b - root) } else { -0.5 * (b + root) };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+    //The element is the cap of the sphere facing away from its center
+    let t = if (direction.z() > 0.0) ^ (radius < 0.0) { t0 } else { t1 };
+    if t < 0.0 {
+        return None;
+    }
+    let normal = Vec3::unit_vector(o + t * direction);
+    Some((t, if normal.dot(direction) > 0.0 { -normal } else { normal }))
+}
+
+//Direction through an interface with ratio eta of the indices before and after it, None on total
+//internal reflection
+fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let wi = -Vec3::unit_vector(direction);
+    let cos_i = wi.dot(normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(eta * -wi + (eta * cos_i - cos_t) * normal)
+}
+
+//Crossing of a ray with the axis along x in lens space, used for the cardinal points
+fn axis_crossing(origin: Vec3, direction: Vec3, x: f32) -> f32 {
+    origin.z() + (x - origin.x()) / direction.x() * direction.z()
+}
+
+//Passes a ray through the interface with its vertex at z, from a medium of index eta_i into one of eta_t
+fn cross(interface: &LensInterface, z: f32, origin: Vec3, direction: Vec3, eta_i: f32, eta_t: f32) -> Option<(Vec3, Vec3)> {
+    if interface.radius == 0.0 {
+        if direction.z() == 0.0 {
+            return None;
+        }
+        let hit = origin + (z - origin.z()) / direction.z() * direction;
+        return if hit.x() * hit.x() + hit.y() * hit.y() > interface.aperture_radius * interface.aperture_radius {
+            None
+        } else {
+            Some((hit, direction))
+        };
+    }
+    let (t, normal) = intersect_element(interface.radius, z + interface.radius, origin, direction)?;
+    let hit = origin + t * direction;
+    if hit.x() * hit.x() + hit.y() * hit.y() > interface.aperture_radius * interface.aperture_radius {
+        return None;
+    }
+    Some((hit, refract(direction, normal, eta_i / eta_t)?))
+}
+
+impl RealisticCamera {
+    //Prescription in millimeters with one interface per line: radius of curvature, thickness,
+    //index of refraction and aperture diameter, optionally followed by the Abbe number of the
+    //glass. A zero radius marks the aperture stop, an index of zero or one air. Lines starting
+    //with # are comments. The lens is left focused as the table has it.
+    pub fn load(path: &Path, lookfrom: Vec3, lookat: Vec3, vup: Vec3, aspect: f32, settings: &PhysicalSettings) -> Result<RealisticCamera, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let scale = 0.001 / settings.scene_unit;
+        let mut interfaces = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line.split_whitespace().map(|value| value.parse::<f32>()).collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            if values.len() < 4 {
+                return Err(format!("{}:{}: expected radius, thickness, ior and aperture", path.display(), number + 1));
+            }
+            let ior = if values[2] == 0.0 { 1.0 } else { values[2] };
+            interfaces.push(LensInterface {
+                radius: values[0] * scale,
+                thickness: values[1] * scale,
+                medium: match values.get(4) {
+                    Some(&abbe) if ior != 1.0 => Dispersion::abbe(ior, abbe),
+                    _ => Dispersion::Constant(ior)
+                },
+                aperture_radius: values[3] * scale / 2.0
+            });
+        }
+        if interfaces.is_empty() {
+            return Err(format!("{}: no lens interfaces", path.display()));
+        }
+        let sensor_width = settings.sensor_width * scale;
+        let camera = RealisticCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup),
+            interfaces,
+            sensor_width,
+            sensor_height: sensor_width / aspect,
+            pupil_radius: 0.0,
+            center_transmission: 0.0,
+            pixel_spacing: None
+        };
+        Ok(camera.with_exit_pupil())
+    }
+
+    //Moves the sensor to bring the plane at distance from it into focus, using the cardinal
+    //points of the lens as a thick lens (pbrt). Fails when the lens blocks the rays finding the
+    //cardinal points or can't focus that close.
+    pub fn focused_at(self, distance: f32) -> Result<RealisticCamera, String> {
+        let x = 0.001 * self.sensor_width.hypot(self.sensor_height);
+        let length = self.front_z();
+        let from_scene = self.trace_from_scene(Vec3::new(x, 0.0, -2.0 * length), Vec3::new(0.0, 0.0, 1.0));
+        let from_film = self.trace_from_film(Vec3::new(x, 0.0, length), Vec3::new(0.0, 0.0, -1.0));
+        let ((scene_origin, scene_direction), (film_origin, film_direction)) = match (from_scene, from_film) {
+            (Some(from_scene), Some(from_film)) => (from_scene, from_film),
+            _ => return Err("the lens blocks the rays locating its focal points".to_string())
+        };
+        //Focal points where the rays cross the axis, principal planes where they meet the lines
+        //they entered on
+        let focal = axis_crossing(scene_origin, scene_direction, 0.0) - axis_crossing(scene_origin, scene_direction, x);
+        let principal_film = axis_crossing(scene_origin, scene_direction, x);
+        let principal_scene = axis_crossing(film_origin, film_direction, x);
+        let z = -distance;
+        let c = (principal_scene - z - principal_film) * (principal_scene - z - 4.0 * focal - principal_film);
+        if c <= 0.0 {
+            return Err(format!("the lens can't focus at a distance of {}", distance));
+        }
+        let delta = 0.5 * (principal_scene - z + principal_film - c.sqrt());
+        let mut interfaces = self.interfaces.clone();
+        let last = interfaces.len() - 1;
+        interfaces[last].thickness += delta;
+        Ok(RealisticCamera {
+            interfaces,
+            ..self
+        }.with_exit_pupil())
+    }
+
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> RealisticCamera {
+        RealisticCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    fn front_z(&self) -> f32 {
+        self.interfaces.iter().map(|interface| interface.thickness).sum()
+    }
+
+    fn rear_z(&self) -> f32 {
+        -self.interfaces[self.interfaces.len() - 1].thickness
+    }
+
+    //Sensor point in lens space of film position (s, t), flipped so the image comes out upright
+    fn film_point(&self, s: f32, t: f32) -> Vec3 {
+        Vec3::new(-(s - 0.5) * self.sensor_width, -(t - 0.5) * self.sensor_height, 0.0)
+    }
+
+    //Rays in lens space leaving the front element, None when an element or the stop blocks them
+    fn trace_from_film(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let (mut origin, mut direction) = (origin, direction);
+        let mut z = 0.0;
+        for i in (0..self.interfaces.len()).rev() {
+            let interface = self.interfaces[i];
+            z -= interface.thickness;
+            let outside = if i > 0 { self.interfaces[i - 1].medium.current_ior() } else { 1.0 };
+            let (origin_next, direction_next) = cross(&interface, z, origin, direction, interface.medium.current_ior(), outside)?;
+            origin = origin_next;
+            direction = direction_next;
+        }
+        Some((origin, direction))
+    }
+
+    fn trace_from_scene(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let (mut origin, mut direction) = (origin, direction);
+        let mut z = -self.front_z();
+        for i in 0..self.interfaces.len() {
+            let interface = self.interfaces[i];
+            let outside = if i > 0 { self.interfaces[i - 1].medium.current_ior() } else { 1.0 };
+            let (origin_next, direction_next) = cross(&interface, z, origin, direction, outside, interface.medium.current_ior())?;
+            origin = origin_next;
+            direction = direction_next;
+            z += interface.thickness;
+        }
+        Some((origin, direction))
+    }
+
+    //Bounds the area of the rear element that light gets through from the sensor, so few of the
+    //traced rays are lost, and measures how much of it is open at the center
+    fn with_exit_pupil(self) -> RealisticCamera {
+        let rear = self.interfaces[self.interfaces.len() - 1].aperture_radius;
+        let rear_z = self.rear_z();
+        let steps = 64;
+        let film_radius = 0.5 * self.sensor_width.hypot(self.sensor_height);
+        let mut reach: f32 = 0.0;
+        let mut center = 0;
+        for k in 0..16 {
+            let film = Vec3::new(film_radius * k as f32 / 15.0, 0.0, 0.0);
+            for i in 0..steps {
+                for j in 0..steps {
+                    let x = rear * (2.0 * (i as f32 + 0.5) / steps as f32 - 1.0);
+                    let y = rear * (2.0 * (j as f32 + 0.5) / steps as f32 - 1.0);
+                    if x * x + y * y > rear * rear {
+                        continue;
+                    }
+                    if self.trace_from_film(film, Vec3::new(x, y, rear_z) - film).is_some() {
+                        reach = reach.max(x.hypot(y));
+                        if k == 0 {
+                            center += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let pupil_radius = (reach + 2.0 * rear / steps as f32).min(rear);
+        let cell = 2.0 * rear / steps as f32;
+        let pupil_cells = ::std::f32::consts::PI * pupil_radius * pupil_radius / (cell * cell);
+        RealisticCamera {
+            pupil_radius,
+            center_transmission: (center as f32 / pupil_cells).min(1.0),
+            ..self
+        }
+    }
+
+    fn to_world(&self, origin: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+        let flip = |v: Vec3| Vec3::new(v.x(), v.y(), -v.z());
+        (self.frame.origin + self.frame.to_world(flip(origin)), self.frame.to_world(flip(direction)))
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn forward(&self) -> Vec3 {
+        self.frame.forward
+    }
+
+    //Rays are traced through the lens instead of drawn from a density, light paths don't connect
+    //to the camera and its strategies carry the whole weight
+    fn pdf_importance(&self, _r: &Ray) -> (f32, f32) {
+        (1.0, 1.0)
+    }
+
+    //Aims at a point on the exit pupil and follows the ray through the elements, rays an element
+    //or the stop blocks are the optical vignetting of the lens
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let p = self.pupil_radius * Aperture::Circular.sample();
+        let pupil_point = Vec3::new(p.x(), p.y(), self.rear_z());
+        let trace = |s: f32, t: f32| {
+            let film = self.film_point(s, t);
+            self.trace_from_film(film, pupil_point - film).map(|(origin, direction)| self.to_world(origin, direction))
+        };
+        let (origin, direction) = trace(s, t)?;
+        let ray = Ray::new(origin, direction);
+        let (ds, dt) = match self.pixel_spacing {
+            Some(spacing) => spacing,
+            None => return Some(ray)
+        };
+        match (trace(s + ds, t), trace(s, t + dt)) {
+            (Some((rx_origin, rx_direction)), Some((ry_origin, ry_direction))) => Some(ray.with_differential(RayDifferential {
+                rx_origin,
+                rx_direction,
+                ry_origin,
+                ry_direction
+            })),
+            _ => Some(ray)
+        }
+    }
+
+    //Rays sample the exit pupil uniformly, this weighs them by the cos^4 falloff of the light
+    //reaching the sensor, relative to the open part of the pupil at the center
+    fn vignetting(&self, s: f32, t: f32) -> f32 {
+        if self.center_transmission <= 0.0 {
+            return 0.0;
+        }
+        let to_pupil = Vec3::new(0.0, 0.0, self.rear_z()) - self.film_point(s, t);
+        let cos = to_pupil.z().abs() / to_pupil.length();
+        cos.powi(4) / self.center_transmission
+    }
+    fn is_dispersive(&self) -> bool {
+        self.interfaces.iter().any(|interface| interface.medium.is_dispersive())
+    }
+}
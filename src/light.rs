@@ -0,0 +1,80 @@
+use vec3::Vec3;
+use ray::Ray;
+use hitable::{Hitable, SurfaceSample};
+use material::random_cosine_direction;
+use sampler;
+
+//All emissive primitives of a scene, sampled proportionally to their area so that
+//every point on every light is chosen with the same density 1 / total_area
+pub struct LightList<'a> {
+    lights: Vec<&'a (dyn Hitable + Sync)>,
+    cdf: Vec<f32>,
+    total_area: f32
+}
+
+pub struct EmissionSample {
+    pub ray: Ray,
+    pub normal: Vec3,
+    pub le: Vec3,
+    pub pdf_pos: f32,
+    pub pdf_dir: f32
+}
+
+//Emitters are two sided, directions are cosine distributed around a randomly chosen side
+pub fn pdf_emission_direction(normal: Vec3, w: Vec3) -> f32 {
+    normal.dot(w).abs() / (2.0 * std::f32::consts::PI)
+}
+
+impl<'a> LightList<'a> {
+    pub fn new(world: &'a (dyn Hitable + Sync)) -> LightList<'a> {
+        let mut lights = Vec::new();
+        world.emitters(&mut lights);
+
+        let mut cdf = Vec::with_capacity(lights.len());
+        let mut total_area = 0.0;
+        for light in lights.iter() {
+            total_area += light.area();
+            cdf.push(total_area);
+        }
+
+        LightList {
+            lights,
+            cdf,
+            total_area
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty() || self.total_area <= 0.0
+    }
+
+    pub fn pdf_position(&self) -> f32 {
+        1.0 / self.total_area
+    }
+
+    pub fn sample(&self) -> Option<SurfaceSample<'a>> {
+        if self.is_empty() {
+            return None;
+        }
+        let target = sampler::random() * self.total_area;
+        let index = self.cdf.iter().position(|&c| c > target).unwrap_or(self.lights.len() - 1);
+        self.lights[index].sample_surface()
+    }
+
+    pub fn sample_emission(&self) -> Option<EmissionSample> {
+        let sample = self.sample()?;
+        let side = if sampler::random() < 0.5 {
+            sample.normal
+        } else {
+            -sample.normal
+        };
+        let direction = random_cosine_direction(side);
+        Some(EmissionSample {
+            ray: Ray::new(sample.p, direction),
+            normal: sample.normal,
+            le: sample.material.emitted(sample.uv.u, sample.uv.v, &sample.p),
+            pdf_pos: self.pdf_position(),
+            pdf_dir: pdf_emission_direction(sample.normal, direction)
+        })
+    }
+}
@@ -1,9 +1,6 @@
 #![allow(dead_code)]
 use std::path::Path;
-use std::fs::File;
-use std::io::BufWriter;
-
-extern crate png;
+use std::sync::Arc;
 
 extern crate rand;
 
@@ -16,6 +13,7 @@ use ray::Ray;
 mod hitable;
 use hitable::Hitable;
 use hitable::BvhNode;
+use hitable::MediumBoundary;
 
 mod sphere;
 use sphere::Sphere;
@@ -24,15 +22,75 @@ mod triangle;
 use triangle::Triangle;
 
 mod material;
-use material::{DiffuseLight, Lambertian};
+use material::{Material, Interior, DiffuseLight, Lambertian, Conductor, RoughDielectric};
+
+mod microfacet;
+
+mod principled;
+use principled::Principled;
+
+mod layered;
+use layered::{MixMaterial, LayeredMaterial};
+
+mod noise;
+use noise::{Noise, NoiseKind, Fractal, NoiseTexture, MarbleTexture, WoodTexture, GraniteTexture, Worley, WorleyTexture, DistanceMetric, WorleyOutput};
+
+mod mtl;
+use mtl::MtlMaterial;
 
 mod camera;
-use camera::Camera;
+use camera::{Camera, PerspectiveCamera, OrthographicCamera, PhysicalSettings};
+
+mod aperture;
+use aperture::Aperture;
+
+mod panoramic;
+use panoramic::{EquirectangularCamera, FisheyeCamera, FisheyeMapping, CubemapCamera, CubemapLayout, CUBE_FACES};
+
+mod lens;
+use lens::RealisticCamera;
 
 mod texture;
-use texture::ConstantTexture;
+use texture::{Texture, ConstantTexture, CheckerTexture, UvCheckerTexture, SolidCheckerTexture, GridTexture, UvDebugTexture, ImageTexture, TextureFilter, ColorRamp, ScaledTexture, MultiplyTexture, MixTexture, TransformTexture, WarpTexture};
+
+mod projection;
+use projection::{Projection, ProjectionTexture};
+
+mod hdr;
+
+mod environment;
+use environment::Environment;
+
+mod bump;
+use bump::BumpMap;
 
 mod aabb;
+use aabb::surrounding_bbox;
+
+mod sampler;
+
+mod film;
+use film::Film;
+
+mod light;
+use light::LightList;
+
+mod bdpt;
+
+mod photon;
+use photon::PhotonSettings;
+
+mod mlt;
+use mlt::MltSettings;
+
+mod medium;
+use medium::{Medium, MediumInterface, HomogeneousMedium, HeterogeneousMedium, HeightFog};
+
+mod grid;
+use grid::{DensityGrid, GridTransform, GridVolume};
+
+mod spectrum;
+use spectrum::{Dispersion, SampledWavelengths};
 
 extern crate rayon;
 use rayon::prelude::*;
@@ -41,59 +99,199 @@ extern crate obj;
 use obj::Obj;
 
 extern crate clap;
-use clap::{Arg, App};
+use clap::{Arg, App, Error, ErrorKind};
 
-fn triangulate(vertices: Vec<Vec3>) -> Vec<Box<dyn Hitable + Sync>> {
+//Texture coordinates are optional, they must be given for all vertices or none
+fn triangulate(vertices: Vec<Vec3>, uvs: Vec<(f32, f32)>, bump: Option<&Arc<BumpMap>>, opacity: Option<&Arc<dyn Texture + Send + Sync>>, material: &dyn Fn() -> Box<dyn Material + Sync>) -> Vec<Box<dyn Hitable + Sync>> {
     assert!(vertices.len() >= 3, "Input face must have at least 3 vertices!");
     let mut output: Vec<Box<dyn Hitable + Sync>> = Vec::new();
+    let triangle = |a: usize, b: usize, c: usize| -> Box<dyn Hitable + Sync> {
+        let edge1 = vertices[b] - vertices[a];
+        let edge2 = vertices[c] - vertices[a];
+        let normal = Vec3::unit_vector(edge1.cross(edge2));
+        let mut triangle = Triangle::new(vertices[a], vertices[b], vertices[c], normal, material());
+        if uvs.len() == vertices.len() {
+            triangle = triangle.with_uvs(uvs[a], uvs[b], uvs[c]);
+        }
+        if let Some(bump) = bump {
+            triangle = triangle.with_bump(bump.clone());
+        }
+        if let Some(opacity) = opacity {
+            triangle = triangle.with_opacity(opacity.clone());
+        }
+        Box::new(triangle)
+    };
 
     //Trivial case: exactly 3 vertices are passed in
     if vertices.len() == 3 {
-        let edge1 = vertices[1] - vertices[0];
-        let edge2 = vertices[2] - vertices[0];
-        let normal = Vec3::unit_vector(edge1.cross(edge2));
-        let material = Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0))));
-        output.push(Box::new(Triangle::new(vertices[0], vertices[1], vertices[2], normal, Box::new(material))));
+        output.push(triangle(0, 1, 2));
     } else { //Non trivial case - parse vertices as triangle fan
         let common_idx = 0;
         let mut first_idx = 1;
         let mut second_idx = 2;
 
-        let common_v = vertices[common_idx];
-
         while second_idx < vertices.len() {
-            let v1 = vertices[first_idx];
-            let v2 = vertices[second_idx];
-            let edge1 = v1 - common_v;
-            let edge2 = v2 - common_v;
-            let normal = Vec3::unit_vector(edge1.cross(edge2));
-            let material = Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0))));
-            output.push(Box::new(Triangle::new(common_v, v1, v2, normal, Box::new(material))));
-            first_idx = first_idx + 1;
-            second_idx = second_idx + 1;
+            output.push(triangle(common_idx, first_idx, second_idx));
+            first_idx += 1;
+            second_idx += 1;
         }
     }
     output
 }
 
-fn color(r : &Ray, world: &Box<dyn Hitable + Sync>, depth: u32) -> Vec3 {
-    let hit_rec = world.hit(0.001, 50.0, r);
-    if hit_rec.is_some() {
-        let hit_rec = hit_rec.unwrap();
+//Parses comma separated components like 1,0.5,-2
+fn parse_vec3(value: &str) -> Vec3 {
+    let components: Vec<f32> = value.split(',').map(|c| c.trim().parse::<f32>().unwrap()).collect();
+    Vec3::new(components[0], components[1], components[2])
+}
+
+//Dielectric volume the ray is in, the highest priority one of all enclosing it
+fn innermost(interiors: &[Interior]) -> Option<&Interior> {
+    //Of equal priorities the last entered wins, as max_by_key returns the last maximum
+    interiors.iter().max_by_key(|interior| interior.priority)
+}
+
+//Spectral values of an RGB color for spectral paths
+fn upsample(wavelengths: Option<SampledWavelengths>, rgb: Vec3) -> Vec3 {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.upsample(rgb),
+        None => rgb
+    }
+}
+
+//medium is the participating medium the ray starts in
+//Spectral paths return the color of their sampled wavelengths
+fn color(r: &Ray, world: &dyn Hitable, environment: Option<&Environment>, medium: Option<&(dyn Medium + Send + Sync)>, max_depth: u32, rr_depth: u32, wavelengths: Option<SampledWavelengths>) -> Vec3 {
+    let mut radiance = Vec3::zero_vector();
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    let mut medium = medium;
+    //Dielectric volumes enclosing the current path vertex, in the order they were entered
+    let mut interiors: Vec<Interior> = Vec::new();
+    let mut wavelengths = wavelengths;
+    let mut depth = 0;
+
+    loop {
+        let hit = world.hit(0.001, 50.0, &ray);
+
+        //Free flight through the current medium up to the next surface
+        if let Some(current) = medium {
+            let t_max = hit.as_ref().map_or(f32::MAX, |hit_rec| hit_rec.t);
+            let medium_sample = current.sample(&ray, t_max);
+            radiance = radiance + throughput * upsample(wavelengths, medium_sample.emission);
+            throughput = throughput * upsample(wavelengths, medium_sample.weight);
+            if let Some(t) = medium_sample.t {
+                if let Some(interior) = innermost(&interiors) {
+                    throughput = throughput * upsample(wavelengths, interior.transmittance(t * ray.direction().length()));
+                }
+                if depth >= max_depth {
+                    break;
+                }
+                let wo = -Vec3::unit_vector(ray.direction());
+                ray = Ray::new(ray.point_at_parameter(t), current.phase().sample(wo));
+                depth += 1;
+                continue;
+            }
+        }
+
+        let hit_rec = match hit {
+            Some(hit_rec) => hit_rec,
+            None => {
+                if let Some(environment) = environment {
+                    radiance = radiance + throughput * upsample(wavelengths, environment.radiance(ray.direction()));
+                }
+                break;
+            }
+        };
         let material = hit_rec.material;
         let normal = hit_rec.normal;
         let point = hit_rec.p;
         let t = hit_rec.t;
-        let scatter_rec = material.scatter(r, t, point, normal);
-        let emitted = material.emitted(0.0, 0.0, &point);
-        if scatter_rec.is_some() && depth < 50 {
-            let scatter_rec = scatter_rec.unwrap();
-            return emitted + scatter_rec.attenuation * color(&scatter_rec.scattered, world, depth + 1);
-        } else {
-            return emitted;
+        let uv = hit_rec.uv;
+        if let Some(interior) = innermost(&interiors) {
+            throughput = throughput * upsample(wavelengths, interior.transmittance(t * ray.direction().length()));
+        }
+
+        //Volumes enclosing the far side of a dielectric surface, which is entered from the front
+        let interior = material.interior();
+        let mut beyond = Vec::new();
+        if let Some(interior) = interior {
+            beyond = interiors.clone();
+            if hit_rec.front_face {
+                beyond.push(interior);
+            } else if let Some(index) = beyond.iter().rposition(|entry| *entry == interior) {
+                beyond.remove(index);
+            }
+            //A higher priority volume overlapping the surface hides it
+            let outer = if hit_rec.front_face { innermost(&interiors) } else { innermost(&beyond) };
+            if outer.is_some_and(|outer| outer.priority > interior.priority) {
+                interiors = beyond;
+                ray = ray.with_origin(point);
+                continue;
+            }
+        }
+
+        radiance = radiance + throughput * upsample(wavelengths, material.emitted(uv.u, uv.v, &point));
+
+        if depth >= max_depth {
+            break;
         }
+        let scatter_rec = match interior {
+            Some(interior) => {
+                let outer_ior = if hit_rec.front_face { innermost(&interiors) } else { innermost(&beyond) }.map_or(1.0, |outer| outer.ior);
+                let eta = if hit_rec.front_face {
+                    interior.ior / outer_ior
+                } else {
+                    outer_ior / interior.ior
+                };
+                material.scatter_between(&ray, t, point, normal, uv, eta)
+            },
+            None => material.scatter(&ray, t, point, normal, uv)
+        };
+        let scatter_rec = match scatter_rec {
+            Some(scatter_rec) => scatter_rec,
+            None => break
+        };
+        //Refracted paths move to the far side
+        if interior.is_some() && scatter_rec.scattered.direction().dot(normal) * ray.direction().dot(normal) > 0.0 {
+            interiors = beyond;
+        }
+        throughput = throughput * upsample(wavelengths, scatter_rec.attenuation);
+        //Only the hero wavelength follows a dispersed path
+        if let (Some(sampled), true) = (wavelengths.as_mut(), material.is_dispersive()) {
+            sampled.terminate_secondary();
+            throughput = sampled.mask(throughput);
+        }
+
+        //Russian roulette: terminate low throughput paths with probability based on their throughput
+        if depth >= rr_depth {
+            let survive_prob = throughput.max_component().min(0.95);
+            if sampler::random() >= survive_prob {
+                break;
+            }
+            throughput = throughput / survive_prob;
+        }
+
+        if let Some(interface) = hit_rec.medium_interface {
+            medium = if hit_rec.leaves_outside(&ray, scatter_rec.scattered.direction()) {
+                interface.outside.as_deref()
+            } else {
+                interface.inside.as_deref()
+            };
+        }
+        //Differentials only follow specular paths, other bounces spread them too widely to matter
+        ray = if scatter_rec.is_specular {
+            ray.specular_differential(scatter_rec.scattered, point, normal)
+        } else {
+            scatter_rec.scattered
+        };
+        depth += 1;
+    }
+
+    match wavelengths {
+        Some(wavelengths) => wavelengths.to_rgb(radiance),
+        None => radiance
     }
-    Vec3::new(0.0, 0.0, 0.0)
     //let unit_direction = Vec3::unit_vector(r.direction());
     //let t = 0.5 * (unit_direction.y() + 1.0);
     //(1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
@@ -119,6 +317,272 @@ fn main() {
                                     .long("height")
                                     .help("Rendered image height")
                                     .takes_value(true))
+                        .arg(Arg::with_name("max_depth")
+                                    .short("d")
+                                    .long("max-depth")
+                                    .help("Maximum number of bounces per path")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("rr_depth")
+                                    .long("rr-depth")
+                                    .help("Bounce after which paths may be terminated by russian roulette")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("integrator")
+                                    .short("i")
+                                    .long("integrator")
+                                    .help("Light transport algorithm")
+                                    .possible_values(&["path", "bdpt", "photon", "sppm", "mlt"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("photons")
+                                    .long("photons")
+                                    .help("Number of photons traced per photon map")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("radius")
+                                    .long("radius")
+                                    .help("Photon gather radius, the initial radius for sppm")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("alpha")
+                                    .long("alpha")
+                                    .help("Fraction of photons kept per sppm iteration, controls radius reduction")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("bootstrap")
+                                    .long("bootstrap")
+                                    .help("Number of paths used to normalize mlt and seed its chains")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("chains")
+                                    .long("chains")
+                                    .help("Number of independent mlt Markov chains")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("large_step")
+                                    .long("large-step")
+                                    .help("Probability of an independent large step mlt mutation")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("sigma")
+                                    .long("sigma")
+                                    .help("Standard deviation of small step mlt mutations")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("material")
+                                    .short("m")
+                                    .long("material")
+                                    .help("Material of the whole loaded mesh, overriding its material library")
+                                    .possible_values(&["diffuse", "gold", "silver", "copper", "aluminium", "glass", "rusted-paint", "marble", "wood", "granite", "cobblestone", "uv-checker", "uv-grid", "uv-debug", "solid-checker"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("roughness")
+                                    .long("roughness")
+                                    .help("Roughness of metal and glass materials, from 0 (smooth) to 1")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("anisotropy")
+                                    .long("anisotropy")
+                                    .help("Anisotropy of the roughness of metal materials, from 0 to 1")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("ior")
+                                    .long("ior")
+                                    .help("Index of refraction of the glass material")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("dispersion")
+                                    .long("dispersion")
+                                    .help("Wavelength dependent index of refraction of the glass material, replacing --ior")
+                                    .possible_values(&["bk7", "fused-silica", "diamond"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("spectral")
+                                    .long("spectral")
+                                    .help("Trace hero wavelengths instead of RGB with the path and mlt integrators, needed for dispersion"))
+                        .arg(Arg::with_name("glass_color")
+                                    .long("glass-color")
+                                    .help("Color of the glass material after light traveled one unit through it, as r,g,b, seen by the path and mlt integrators")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("medium_scattering")
+                                    .long("medium-scattering")
+                                    .help("Scattering coefficients of a homogeneous medium filling the glass material, as r,g,b, seen by the path and mlt integrators")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("medium_absorption")
+                                    .long("medium-absorption")
+                                    .help("Absorption coefficients of the medium filling the glass material, as r,g,b")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("medium_g")
+                                    .long("medium-g")
+                                    .help("Henyey-Greenstein asymmetry of the medium filling the glass material")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("fog")
+                                    .long("fog")
+                                    .help("Scattering coefficient of a homogeneous fog surrounding the camera, seen by the path and mlt integrators")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("fog_g")
+                                    .long("fog-g")
+                                    .help("Henyey-Greenstein asymmetry of the fog, positive scatters forward")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("fog_falloff")
+                                    .long("fog-falloff")
+                                    .help("Exponential falloff of the fog density with height above the lowest point of the scene")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("environment")
+                                    .long("environment")
+                                    .help("Equirectangular image lighting the scene from afar, seen by the path and mlt integrators")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("environment_intensity")
+                                    .long("environment-intensity")
+                                    .help("Factor the radiance of the environment image is multiplied by")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("environment_rotation")
+                                    .long("environment-rotation")
+                                    .help("Turn of the environment about the vertical axis in degrees")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume")
+                                    .long("volume")
+                                    .help("Density grid to place in the scene, a raw data header or a dense text grid, seen by the path and mlt integrators")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_position")
+                                    .long("volume-position")
+                                    .help("World position of the density grid corner as x,y,z")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_size")
+                                    .long("volume-size")
+                                    .help("World size of the longest density grid axis")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_rotation")
+                                    .long("volume-rotation")
+                                    .help("Rotation of the density grid around the y axis in degrees")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_density")
+                                    .long("volume-density")
+                                    .help("Extinction coefficient of the density grid at density one")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_albedo")
+                                    .long("volume-albedo")
+                                    .help("Fraction of the extinction of the density grid that scatters")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("volume_emission")
+                                    .long("volume-emission")
+                                    .help("Scale of the emission or temperature channel of the density grid")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("seed")
+                                    .long("seed")
+                                    .help("Seed of the noise in procedural materials")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("frequency")
+                                    .long("frequency")
+                                    .help("Cells per unit of texture coordinates of uv patterns, or per world unit of solid-checker")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("noise")
+                                    .long("noise")
+                                    .help("Gradient noise of procedural materials")
+                                    .possible_values(&["perlin", "simplex"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("coat")
+                                    .long("coat")
+                                    .help("Index of refraction of a clear coat layered over every material, no coat when 0")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("coat_roughness")
+                                    .long("coat-roughness")
+                                    .help("Roughness of the clear coat, from 0 (smooth) to 1")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("coat_color")
+                                    .long("coat-color")
+                                    .help("Color of the coat after light passed through it once, as r,g,b")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("texture_filter")
+                                    .long("texture-filter")
+                                    .help("Filtering of image textures over the pixel footprint")
+                                    .possible_values(&["bilinear", "trilinear", "ewa"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("projection")
+                                    .long("projection")
+                                    .help("Generates texture coordinates from positions for the diffuse map and the uv patterns")
+                                    .possible_values(&["planar", "cylindrical", "spherical", "box", "triplanar"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("projection_scale")
+                                    .long("projection-scale")
+                                    .help("World units covered by one repetition of a projected texture")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("bump_scale")
+                                    .long("bump-scale")
+                                    .help("Height in scene units of a white texel in bump maps, multiplied by their -bm option")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("camera")
+                                    .long("camera")
+                                    .help("Projection of the camera")
+                                    .possible_values(&["perspective", "physical", "orthographic", "equirectangular", "fisheye", "cubemap", "ods", "lens"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("view_width")
+                                    .long("view-width")
+                                    .help("Width in scene units of the view plane of the orthographic camera, by default framing what the perspective camera sees at its focus")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("aperture")
+                                    .long("aperture")
+                                    .help("Lens diameter of the perspective camera, focused at the point it looks at")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("blades")
+                                    .long("blades")
+                                    .help("Number of aperture blades, a circular aperture when fewer than 3")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("blade_rotation")
+                                    .long("blade-rotation")
+                                    .help("Rotation of the polygonal aperture in degrees")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("blade_curvature")
+                                    .long("blade-curvature")
+                                    .help("Bulge of the aperture blades from straight at 0 to circular at 1")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("aperture_image")
+                                    .long("aperture-image")
+                                    .help("Grayscale image of the aperture transmission, replacing the blades")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("cat_eye")
+                                    .long("cat-eye")
+                                    .help("Offset of the lens barrel at the image corners in aperture radii, clipping defocused highlights into cat's eyes")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("focal_length")
+                                    .long("focal-length")
+                                    .help("Focal length of the physical camera in millimeters")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("sensor_width")
+                                    .long("sensor-width")
+                                    .help("Sensor width of the physical camera in millimeters")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("f_number")
+                                    .long("f-number")
+                                    .help("Aperture of the physical camera as a ratio of its focal length")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("shutter")
+                                    .long("shutter")
+                                    .help("Exposure time of the physical camera in seconds, as a decimal or a fraction like 1/125")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("iso")
+                                    .long("iso")
+                                    .help("Sensitivity of the physical camera, exposing for radiance in cd/m^2")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("scene_unit")
+                                    .long("scene-unit")
+                                    .help("Length of a scene unit in meters, sizing the lens of the physical camera")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("vignetting")
+                                    .long("vignetting")
+                                    .help("Natural cos^4 falloff towards the image edges of perspective cameras"))
+                        .arg(Arg::with_name("fov")
+                                    .long("fov")
+                                    .help("Field of view of the fisheye camera in degrees, up to 360")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("fisheye")
+                                    .long("fisheye")
+                                    .help("Mapping of angles to the image circle of the fisheye camera")
+                                    .possible_values(&["equidistant", "equisolid"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("cubemap_layout")
+                                    .long("cubemap-layout")
+                                    .help("Six images named after their faces, rendered side by side at 6:1, or one cross at 4:3")
+                                    .possible_values(&["faces", "cross"])
+                                    .takes_value(true))
+                        .arg(Arg::with_name("ipd")
+                                    .long("ipd")
+                                    .help("Interpupillary distance in scene units of the ods camera, its eyes are stacked left over right")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("lens")
+                                    .long("lens")
+                                    .help("Lens prescription of the lens camera, a table of radius, thickness, ior, aperture and optionally Abbe number per interface in millimeters")
+                                    .takes_value(true))
+                        .arg(Arg::with_name("focus_distance")
+                                    .long("focus-distance")
+                                    .help("Distance in scene units from the sensor the lens camera focuses at, by default the point it looks at")
+                                    .takes_value(true))
                         .arg(Arg::with_name("output")
                                     .short("o")
                                     .long("output")
@@ -131,10 +595,180 @@ fn main() {
     let image_width = matches.value_of("width").unwrap_or("480");
     let image_height = matches.value_of("height").unwrap_or("270");
     let output_filename = matches.value_of("output").unwrap_or("output.png");
+    let max_depth = matches.value_of("max_depth").unwrap_or("50");
+    let rr_depth = matches.value_of("rr_depth").unwrap_or("5");
+    let integrator = matches.value_of("integrator").unwrap_or("path");
+    //The other integrators would silently render without these
+    if integrator != "path" && integrator != "mlt" {
+        for option in ["fog", "spectral", "environment", "glass_color", "volume", "medium_scattering", "medium_absorption"].iter() {
+            if matches.is_present(option) {
+                Error::with_description(&format!("--{} is only supported by the path and mlt integrators", option.replace('_', "-")), ErrorKind::ArgumentConflict).exit();
+            }
+        }
+    }
+    let photon_count = matches.value_of("photons").unwrap_or("200000");
+    let radius = matches.value_of("radius").unwrap_or("0.05");
+    let alpha = matches.value_of("alpha").unwrap_or("0.7");
+    let bootstrap_samples = matches.value_of("bootstrap").unwrap_or("100000");
+    let chains = matches.value_of("chains").unwrap_or("1000");
+    let large_step_probability = matches.value_of("large_step").unwrap_or("0.3");
+    let sigma = matches.value_of("sigma").unwrap_or("0.01");
+    let material_name = matches.value_of("material");
+    let roughness = matches.value_of("roughness").unwrap_or("0.3");
+    let anisotropy = matches.value_of("anisotropy").unwrap_or("0.0");
+    let ior = matches.value_of("ior").unwrap_or("1.5");
+    let dispersion = matches.value_of("dispersion");
+    let spectral = matches.is_present("spectral");
+    let glass_color = matches.value_of("glass_color").unwrap_or("1,1,1");
+    let medium_scattering = matches.value_of("medium_scattering");
+    let medium_absorption = matches.value_of("medium_absorption");
+    let medium_g = matches.value_of("medium_g").unwrap_or("0.0");
+    //Only the glass material lets rays into the medium
+    if (medium_scattering.is_some() || medium_absorption.is_some()) && material_name != Some("glass") {
+        Error::with_description("--medium-scattering and --medium-absorption fill the glass material, use them with --material glass", ErrorKind::ArgumentConflict).exit();
+    }
+    let fog = matches.value_of("fog").unwrap_or("0.0");
+    let fog_g = matches.value_of("fog_g").unwrap_or("0.0");
+    let fog_falloff = matches.value_of("fog_falloff").unwrap_or("0.0");
+    let environment = matches.value_of("environment");
+    let environment_intensity = matches.value_of("environment_intensity").unwrap_or("1.0");
+    let environment_rotation = matches.value_of("environment_rotation").unwrap_or("0.0");
+    let volume = matches.value_of("volume");
+    let volume_position = matches.value_of("volume_position").unwrap_or("0,0,0");
+    let volume_size = matches.value_of("volume_size").unwrap_or("1.0");
+    let volume_rotation = matches.value_of("volume_rotation").unwrap_or("0.0");
+    let volume_density = matches.value_of("volume_density").unwrap_or("10.0");
+    let volume_albedo = matches.value_of("volume_albedo").unwrap_or("0.9");
+    let volume_emission = matches.value_of("volume_emission").unwrap_or("1.0");
+    let bump_scale = matches.value_of("bump_scale").unwrap_or("1.0");
+    let seed = matches.value_of("seed").unwrap_or("0");
+    let frequency = matches.value_of("frequency").unwrap_or("8");
+    let noise_kind = match matches.value_of("noise") {
+        Some("simplex") => NoiseKind::Simplex,
+        _ => NoiseKind::Perlin
+    };
+    let texture_filter = match matches.value_of("texture_filter") {
+        Some("bilinear") => TextureFilter::Bilinear,
+        Some("trilinear") => TextureFilter::Trilinear,
+        _ => TextureFilter::Ewa
+    };
+    let projection = match matches.value_of("projection") {
+        Some("planar") => Some(Projection::Planar),
+        Some("cylindrical") => Some(Projection::Cylindrical),
+        Some("spherical") => Some(Projection::Spherical),
+        Some("box") => Some(Projection::Box),
+        Some("triplanar") => Some(Projection::Triplanar(4.0)),
+        _ => None
+    };
+    let projection_scale = matches.value_of("projection_scale").unwrap_or("1.0");
+    let coat = matches.value_of("coat").unwrap_or("0.0");
+    let coat_roughness = matches.value_of("coat_roughness").unwrap_or("0.0");
+    let coat_color = matches.value_of("coat_color").unwrap_or("1,1,1");
+    let camera_kind = matches.value_of("camera").unwrap_or("perspective");
+    let view_width = matches.value_of("view_width");
+    let aperture = matches.value_of("aperture").unwrap_or("0.0");
+    let blades = matches.value_of("blades").unwrap_or("0");
+    let blade_rotation = matches.value_of("blade_rotation").unwrap_or("0.0");
+    let blade_curvature = matches.value_of("blade_curvature").unwrap_or("0.0");
+    let aperture_image = matches.value_of("aperture_image");
+    let cat_eye = matches.value_of("cat_eye").unwrap_or("0.0");
+    let focal_length = matches.value_of("focal_length").unwrap_or("50");
+    let sensor_width = matches.value_of("sensor_width").unwrap_or("36");
+    let f_number = matches.value_of("f_number").unwrap_or("2.8");
+    let shutter = matches.value_of("shutter").unwrap_or("1/60");
+    let iso = matches.value_of("iso").unwrap_or("100");
+    let scene_unit = matches.value_of("scene_unit").unwrap_or("1.0");
+    let vignetting = matches.is_present("vignetting");
+    let fov = matches.value_of("fov").unwrap_or("180");
+    let fisheye_mapping = match matches.value_of("fisheye") {
+        Some("equisolid") => FisheyeMapping::Equisolid,
+        _ => FisheyeMapping::Equidistant
+    };
+    let cubemap_layout = match matches.value_of("cubemap_layout") {
+        Some("faces") => CubemapLayout::Faces,
+        _ => CubemapLayout::Cross
+    };
+    let ipd = matches.value_of("ipd").unwrap_or("0.064");
+    let lens = matches.value_of("lens");
+    if camera_kind == "lens" && lens.is_none() {
+        Error::with_description("--camera lens needs a prescription given with --lens", ErrorKind::MissingRequiredArgument).exit();
+    }
+    let focus_distance = matches.value_of("focus_distance");
 
     let samples_per_pixel = samples_per_pixel.parse::<usize>().unwrap();
     let image_width = image_width.parse::<u32>().unwrap();
     let image_height = image_height.parse::<u32>().unwrap();
+    //Cubemap faces are square cells of the film
+    if camera_kind == "cubemap" {
+        let (columns, rows, name) = match cubemap_layout {
+            CubemapLayout::Faces => (6, 1, "faces"),
+            CubemapLayout::Cross => (4, 3, "cross")
+        };
+        if image_width % columns != 0 || image_height % rows != 0 || image_width / columns != image_height / rows {
+            Error::with_description(&format!("--camera cubemap needs square faces, the {} layout takes {} by {} of them, e.g. -w {} -h {}",
+                name, columns, rows, image_height / rows * columns, image_height / rows * rows), ErrorKind::ArgumentConflict).exit();
+        }
+    }
+    let max_depth = max_depth.parse::<u32>().unwrap();
+    let rr_depth = rr_depth.parse::<u32>().unwrap();
+    let photon_count = photon_count.parse::<usize>().unwrap();
+    let radius = radius.parse::<f32>().unwrap();
+    let alpha = alpha.parse::<f32>().unwrap();
+    let bootstrap_samples = bootstrap_samples.parse::<usize>().unwrap();
+    let chains = chains.parse::<usize>().unwrap();
+    let large_step_probability = large_step_probability.parse::<f32>().unwrap();
+    let sigma = sigma.parse::<f32>().unwrap();
+    let roughness = roughness.parse::<f32>().unwrap();
+    let anisotropy = anisotropy.parse::<f32>().unwrap();
+    let ior = ior.parse::<f32>().unwrap();
+    let dispersion = match dispersion {
+        Some("bk7") => Dispersion::bk7(),
+        Some("fused-silica") => Dispersion::fused_silica(),
+        Some("diamond") => Dispersion::diamond(),
+        _ => Dispersion::Constant(ior)
+    };
+    let glass_color = parse_vec3(glass_color);
+    let medium_scattering = medium_scattering.map(parse_vec3);
+    let medium_absorption = medium_absorption.map(parse_vec3);
+    let medium_g = medium_g.parse::<f32>().unwrap();
+    let fog = fog.parse::<f32>().unwrap();
+    let fog_g = fog_g.parse::<f32>().unwrap();
+    let fog_falloff = fog_falloff.parse::<f32>().unwrap();
+    let volume_position = parse_vec3(volume_position);
+    let environment_intensity = environment_intensity.parse::<f32>().unwrap();
+    let environment_rotation = environment_rotation.parse::<f32>().unwrap();
+    let volume_size = volume_size.parse::<f32>().unwrap();
+    let volume_rotation = volume_rotation.parse::<f32>().unwrap();
+    let volume_density = volume_density.parse::<f32>().unwrap();
+    let volume_albedo = volume_albedo.parse::<f32>().unwrap();
+    let volume_emission = volume_emission.parse::<f32>().unwrap();
+    let bump_scale = bump_scale.parse::<f32>().unwrap();
+    let seed = seed.parse::<u64>().unwrap();
+    let frequency = frequency.parse::<f32>().unwrap();
+    let projection_scale = projection_scale.parse::<f32>().unwrap();
+    let coat = coat.parse::<f32>().unwrap();
+    let coat_roughness = coat_roughness.parse::<f32>().unwrap();
+    let coat_color = parse_vec3(coat_color);
+    let view_width = view_width.map(|width| width.parse::<f32>().unwrap());
+    let aperture = aperture.parse::<f32>().unwrap();
+    let blades = blades.parse::<u32>().unwrap();
+    let blade_rotation = blade_rotation.parse::<f32>().unwrap();
+    let blade_curvature = blade_curvature.parse::<f32>().unwrap();
+    let cat_eye = cat_eye.parse::<f32>().unwrap();
+    let physical = PhysicalSettings {
+        focal_length: focal_length.parse::<f32>().unwrap(),
+        sensor_width: sensor_width.parse::<f32>().unwrap(),
+        f_number: f_number.parse::<f32>().unwrap(),
+        shutter: match shutter.split_once('/') {
+            Some((numerator, denominator)) => numerator.parse::<f32>().unwrap() / denominator.parse::<f32>().unwrap(),
+            None => shutter.parse::<f32>().unwrap()
+        },
+        iso: iso.parse::<f32>().unwrap(),
+        scene_unit: scene_unit.parse::<f32>().unwrap()
+    };
+    let fov = fov.parse::<f32>().unwrap();
+    let ipd = ipd.parse::<f32>().unwrap();
+    let focus_distance = focus_distance.map(|distance| distance.parse::<f32>().unwrap());
 
     println!("Generating a {}x{}@{}spp render of {}, saving to {}", image_width, image_height, samples_per_pixel, filename, output_filename);
 
@@ -142,71 +776,308 @@ fn main() {
     let mut world: Vec<Box<dyn Hitable + Sync>> = Vec::new();
 
     let obj_file = Obj::<obj::SimplePolygon>::load(Path::new(filename)).unwrap();
+    let mut mtl_materials: Vec<MtlMaterial> = Vec::new();
+    for library in obj_file.material_libs.iter() {
+        match mtl::load(&obj_file.path.join(library)) {
+            Ok(mut materials) => mtl_materials.append(&mut materials),
+            Err(error) => println!("Skipping material library {}", error)
+        }
+    }
+
     for object in obj_file.objects.iter() {
         for group in object.groups.iter() {
+            let mtl_material = group.material.as_ref().and_then(|material| mtl_materials.iter().find(|m| m.name == material.name));
+            let load_color = |file: &String| -> Option<Arc<dyn Texture + Send + Sync>> {
+                match ImageTexture::load(&obj_file.path.join(file), true) {
+                    Ok(texture) => Some(Arc::new(texture.with_filter(texture_filter))),
+                    Err(error) => {
+                        println!("Skipping color map {}", error);
+                        None
+                    }
+                }
+            };
+            let diffuse_map = mtl_material.and_then(|mtl_material| mtl_material.diffuse_map.as_ref()).and_then(load_color);
+            let emission_map = mtl_material.and_then(|mtl_material| mtl_material.emission_map.as_ref()).and_then(load_color);
+            //Textures laid out by uv, or by position with a projection
+            let mapped = |texture: Box<dyn Texture + Sync>| -> Box<dyn Texture + Sync> {
+                match projection {
+                    Some(projection) => {
+                        let scale = 1.0 / projection_scale;
+                        Box::new(TransformTexture::new(Box::new(ProjectionTexture::new(texture, projection))).scale(Vec3::new(scale, scale, scale)))
+                    },
+                    None => texture
+                }
+            };
+            let base_material = || -> Box<dyn Material + Sync> {
+                match (material_name, mtl_material) {
+                    (Some("gold"), _) => Box::new(Conductor::gold(roughness, anisotropy)),
+                    (Some("silver"), _) => Box::new(Conductor::silver(roughness, anisotropy)),
+                    (Some("copper"), _) => Box::new(Conductor::copper(roughness, anisotropy)),
+                    (Some("aluminium"), _) => Box::new(Conductor::aluminium(roughness, anisotropy)),
+                    (Some("glass"), _) => Box::new(RoughDielectric::new(ior, Box::new(ConstantTexture::new(Vec3::new(roughness, roughness, roughness)))).with_absorption(glass_color, 1.0).with_dispersion(dispersion)),
+                    //Patches of rust on glossy red paint
+                    (Some("rusted-paint"), _) => {
+                        let paint = LayeredMaterial::new(Box::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.6, 0.05, 0.04))))), 1.5, 0.05);
+                        let rust = Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.3, 0.12, 0.05))));
+                        let mask = CheckerTexture::new(Box::new(ConstantTexture::new(Vec3::zero_vector())), Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0))));
+                        Box::new(MixMaterial::new(Box::new(paint), Box::new(rust), Box::new(mask)))
+                    },
+                    (Some("marble"), _) => Box::new(Lambertian::new(Box::new(MarbleTexture::new(Noise::new(noise_kind, seed), 6.0, Fractal::new(6), ColorRamp::marble())))),
+                    (Some("wood"), _) => Box::new(Lambertian::new(Box::new(WoodTexture::new(Noise::new(noise_kind, seed), 8.0, Fractal::new(4), ColorRamp::wood())))),
+                    //Cellular stones with noisy outlines, built from texture combinators
+                    (Some("cobblestone"), _) => {
+                        let white = Vec3::new(1.0, 1.0, 1.0);
+                        let mortar_mask = WorleyTexture::new(Worley::new(seed, DistanceMetric::Euclidean), 3.0, WorleyOutput::F2MinusF1, ColorRamp::new(vec![(0.02, white), (0.06, Vec3::zero_vector())]));
+                        let warp = NoiseTexture::new(Noise::new(noise_kind, seed + 1), 2.0, Fractal::new(3), ColorRamp::between(Vec3::zero_vector(), white));
+                        let mortar_mask = TransformTexture::new(Box::new(WarpTexture::new(Box::new(mortar_mask), Box::new(warp), 0.15))).rotate(Vec3::new(0.0, 1.0, 0.0), 30.0);
+                        let variation = ScaledTexture::new(Box::new(NoiseTexture::new(Noise::new(noise_kind, seed + 2), 1.5, Fractal::new(2), ColorRamp::between(Vec3::zero_vector(), white))), Vec3::new(0.6, 0.6, 0.6)).with_offset(Vec3::new(0.6, 0.6, 0.6));
+                        let stone = MultiplyTexture::new(Box::new(GraniteTexture::new(Noise::new(noise_kind, seed), 20.0, Fractal::new(5).with_lacunarity(2.5), ColorRamp::granite())), Box::new(variation));
+                        let mortar = ConstantTexture::new(Vec3::new(0.2, 0.19, 0.17));
+                        Box::new(Lambertian::new(Box::new(MixTexture::new(Box::new(stone), Box::new(mortar), Box::new(mortar_mask)))))
+                    },
+                    (Some("granite"), _) => Box::new(Lambertian::new(Box::new(GraniteTexture::new(Noise::new(noise_kind, seed), 20.0, Fractal::new(5).with_lacunarity(2.5), ColorRamp::granite())))),
+                    (Some("uv-checker"), _) => {
+                        let checker = UvCheckerTexture::new(Box::new(ConstantTexture::new(Vec3::new(0.1, 0.1, 0.1))), Box::new(ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9))));
+                        Box::new(Lambertian::new(mapped(Box::new(checker.with_frequency(frequency, frequency)))))
+                    },
+                    (Some("uv-grid"), _) => {
+                        let grid = GridTexture::new(Box::new(ConstantTexture::new(Vec3::new(0.05, 0.05, 0.05))), Box::new(ConstantTexture::new(Vec3::new(0.8, 0.8, 0.8))));
+                        Box::new(Lambertian::new(mapped(Box::new(grid.with_frequency(frequency, frequency)))))
+                    },
+                    (Some("uv-debug"), _) => Box::new(Lambertian::new(mapped(Box::new(UvDebugTexture::new().with_frequency(frequency, frequency))))),
+                    (Some("solid-checker"), _) => {
+                        let checker = SolidCheckerTexture::new(Box::new(ConstantTexture::new(Vec3::new(0.1, 0.1, 0.1))), Box::new(ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9))), 1.0 / frequency);
+                        Box::new(Lambertian::new(Box::new(checker)))
+                    },
+                    //Ke scales the emission map, which defaults to white
+                    (None, Some(mtl_material)) => match (&emission_map, mtl_material.emission()) {
+                        (Some(emission_map), emission) => {
+                            let emission = emission.unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0));
+                            Box::new(DiffuseLight::new(Box::new(ScaledTexture::new(mapped(Box::new(emission_map.clone())), emission))))
+                        },
+                        (None, Some(emission)) => Box::new(DiffuseLight::new(Box::new(ConstantTexture::new(emission)))),
+                        (None, None) => Box::new(Principled::new(mtl_material.principled(diffuse_map.clone().map(|texture| mapped(Box::new(texture))))))
+                    },
+                    _ => Box::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0)))))
+                }
+            };
+            let material = || -> Box<dyn Material + Sync> {
+                let base = base_material();
+                if coat > 0.0 && !base.is_emissive() {
+                    Box::new(LayeredMaterial::new(base, coat, coat_roughness).with_color(coat_color))
+                } else {
+                    base
+                }
+            };
+            //Normal maps take precedence over height maps
+            let bump = mtl_material.and_then(|mtl_material| {
+                let load = |file: &str| match ImageTexture::load(&obj_file.path.join(file), false) {
+                    Ok(texture) => Some(Arc::new(texture)),
+                    Err(error) => {
+                        println!("Skipping bump map {}", error);
+                        None
+                    }
+                };
+                match (&mtl_material.normal_map, &mtl_material.bump_map) {
+                    (Some(file), _) => load(file).map(|texture| BumpMap::Normal(texture)),
+                    (None, Some((file, multiplier))) => load(file).map(|texture| BumpMap::Height(texture, bump_scale * multiplier)),
+                    (None, None) => None
+                }
+            }).map(Arc::new);
+            let opacity = mtl_material.and_then(|mtl_material| mtl_material.opacity()).and_then(|(file, dissolve)| -> Option<Arc<dyn Texture + Send + Sync>> {
+                match file {
+                    Some(file) => match ImageTexture::load_opacity(&obj_file.path.join(file), dissolve) {
+                        Ok(texture) => Some(Arc::new(texture)),
+                        Err(error) => {
+                            println!("Skipping opacity map {}", error);
+                            None
+                        }
+                    },
+                    None => Some(Arc::new(ConstantTexture::new(Vec3::new(dissolve, dissolve, dissolve))))
+                }
+            });
             for polygon in group.polys.iter() {
                 let mut vertices: Vec<Vec3> = Vec::new();
+                let mut uvs: Vec<(f32, f32)> = Vec::new();
                 for vertex in polygon.iter() {
                     let index = vertex.0;
                     let position = obj_file.position[index];
                     vertices.push(Vec3::new(position[0], position[1], position[2]));
+                    if let Some(texture_index) = vertex.1 {
+                        let uv = obj_file.texture[texture_index];
+                        uvs.push((uv[0], uv[1]));
+                    }
                 }
-                world.append(&mut triangulate(vertices));
+                world.append(&mut triangulate(vertices, uvs, bump.as_ref(), opacity.as_ref(), &material));
             }
         }
     }
 
+    let volume = volume.map(|volume| {
+        //Load with a unit scale first to find the aspect ratio of the grid, voxels stay cubic
+        let grid = DensityGrid::load(Path::new(volume), GridTransform::new(volume_position, Vec3::new(1.0, 1.0, 1.0), volume_rotation), volume_emission).unwrap();
+        let resolution = grid.resolution();
+        let longest = resolution.iter().cloned().max().unwrap() as f32;
+        let scale = (volume_size / longest) * Vec3::new(resolution[0] as f32, resolution[1] as f32, resolution[2] as f32);
+        let transform = GridTransform::new(volume_position, scale, volume_rotation);
+        let grid = grid.with_transform(transform);
+
+        let sigma_s = volume_density * volume_albedo;
+        let sigma_a = volume_density - sigma_s;
+        (transform, HeterogeneousMedium::new(grid, Vec3::new(sigma_a, sigma_a, sigma_a), Vec3::new(sigma_s, sigma_s, sigma_s), 0.0))
+    });
+
+    let mesh_count = world.len();
+
     world.push(Box::new(Sphere::new(Vec3::new(0.0, 0.0, 2.0), 0.5, Box::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(2.0, 2.0, 2.0))))))));
 
+    //The camera sits in this medium, it fills the scene except for closed media boundaries and
+    //surrounds the volume
+    let camera_medium: Option<Arc<dyn Medium + Send + Sync>> = if fog > 0.0 && fog_falloff > 0.0 {
+        let bbox = world.iter().map(|hitable| hitable.bounding_box()).chain(volume.iter().map(|(transform, _)| transform.bounds())).reduce(surrounding_bbox).unwrap();
+        let height_fog = HeightFog::new(fog, bbox.min().y(), fog_falloff, bbox);
+        Some(Arc::new(HeterogeneousMedium::new(height_fog, Vec3::zero_vector(), Vec3::new(1.0, 1.0, 1.0), fog_g)))
+    } else if fog > 0.0 {
+        Some(Arc::new(HomogeneousMedium::new(Vec3::zero_vector(), Vec3::new(fog, fog, fog), fog_g)))
+    } else {
+        None
+    };
+
+    if let Some((transform, medium)) = volume {
+        world.push(Box::new(GridVolume::new(transform, Arc::new(medium), camera_medium.clone())));
+    }
+
+    //The mesh is made of glass here, its triangles bound the medium filling it
+    if medium_scattering.is_some() || medium_absorption.is_some() {
+        let sigma_a = medium_absorption.unwrap_or_else(Vec3::zero_vector);
+        let sigma_s = medium_scattering.unwrap_or_else(Vec3::zero_vector);
+        let interface = MediumInterface::new(Some(Arc::new(HomogeneousMedium::new(sigma_a, sigma_s, medium_g))), camera_medium.clone());
+        let rest = world.split_off(mesh_count);
+        world = world.into_iter().map(|triangle| -> Box<dyn Hitable + Sync> { Box::new(MediumBoundary::new(triangle, interface.clone())) }).collect();
+        world.extend(rest);
+    }
+
     let bvh_tree: Box<dyn Hitable + Sync> = Box::new(BvhNode::new(world));
+    let environment = environment.map(|file| {
+        let texture = ImageTexture::load(Path::new(file), true).unwrap();
+        Environment::new(Box::new(texture)).with_rotation(environment_rotation).with_intensity(environment_intensity)
+    });
+    let lights = LightList::new(&*bvh_tree);
     //Setup camera
-    let lookfrom = 3.0 * Vec3::new(-2.26788425, 0.320256859, 1.83503199);
-    let lookat = Vec3::new(-1.33643341, 0.320256859, 1.47116470);
+    let lookfrom = 3.0 * Vec3::new(-2.267_884_3, 0.320_256_87, 1.835_032);
+    let lookat = Vec3::new(-1.336_433_4, 0.320_256_87, 1.471_164_7);
     let focus_dist = (lookfrom - lookat).length();
-    let aperture = 0.0;
-    let camera = Camera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), 20.0, image_width as f32 / image_height as f32, aperture, focus_dist);
+    let aperture_shape = match aperture_image {
+        Some(file) => Aperture::load(Path::new(file)).unwrap(),
+        None => Aperture::polygon(blades, blade_rotation, blade_curvature)
+    };
+    let vfov = 20.0f32;
+    let aspect = image_width as f32 / image_height as f32;
+    let camera: Box<dyn Camera + Sync> = match camera_kind {
+        "orthographic" => {
+            let width = view_width.unwrap_or(2.0 * (vfov.to_radians() / 2.0).tan() * focus_dist * aspect);
+            Box::new(OrthographicCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), width, aspect)
+                .with_differentials(image_width, image_height, samples_per_pixel))
+        },
+        "equirectangular" => Box::new(EquirectangularCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0))
+            .with_differentials(image_width, image_height, samples_per_pixel)),
+        "ods" => Box::new(EquirectangularCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0)).with_stereo(ipd)
+            .with_differentials(image_width, image_height, samples_per_pixel)),
+        "fisheye" => Box::new(FisheyeCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), fov, fisheye_mapping, aspect)
+            .with_differentials(image_width, image_height, samples_per_pixel)),
+        "cubemap" => Box::new(CubemapCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), cubemap_layout)
+            .with_differentials(image_width, image_height, samples_per_pixel)),
+        "lens" => {
+            let camera = RealisticCamera::load(Path::new(lens.unwrap()), lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), aspect, &physical)
+                .and_then(|camera| camera.focused_at(focus_distance.unwrap_or(focus_dist)))
+                .unwrap_or_else(|e| Error::with_description(&e, ErrorKind::InvalidValue).exit());
+            Box::new(camera.with_differentials(image_width, image_height, samples_per_pixel))
+        },
+        "physical" => Box::new(PerspectiveCamera::physical(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), aspect, focus_dist, &physical)
+            .with_aperture(aperture_shape)
+            .with_cat_eye(cat_eye)
+            .with_natural_vignetting(vignetting)
+            .with_differentials(image_width, image_height, samples_per_pixel)),
+        _ => Box::new(PerspectiveCamera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), vfov, aspect, aperture, focus_dist)
+            .with_aperture(aperture_shape)
+            .with_cat_eye(cat_eye)
+            .with_natural_vignetting(vignetting)
+            .with_differentials(image_width, image_height, samples_per_pixel))
+    };
+    let exposure = if camera_kind == "physical" { physical.exposure() } else { 1.0 };
+    let camera_medium = camera_medium.as_deref();
+
+    let photon_settings = PhotonSettings {
+        photon_count,
+        radius,
+        alpha,
+        max_depth
+    };
+    let mlt_settings = MltSettings {
+        bootstrap_samples,
+        chains,
+        large_step_probability,
+        sigma
+    };
 
     //Generate image
-    let mut data = Vec::new();
+    let mut film = Film::new(image_width, image_height).with_exposure(exposure);
 
     //Save start time
     let start_time = std::time::Instant::now();
 
-    for y in (0..image_height).rev() {
-        for x in 0..image_width {
-            let mut samples = vec![Vec3::zero_vector(); samples_per_pixel];
-
-            samples.par_iter_mut().for_each( |sample| {
-                let u = (x as f32 + rand::random::<f32>()) / image_width as f32;
-                let v = (y as f32 + rand::random::<f32>()) / image_height as f32;
-
-                let r = camera.get_ray(u, v);
-                *sample = color(&r, &bvh_tree, 0);
-            });
+    //Radiance arriving at film position (u, v). Camera rays are generated within the wavelength
+    //scope, so lenses disperse them too and only the hero wavelength follows the dispersed ray.
+    let trace = |u: f32, v: f32| if spectral {
+        let mut wavelengths = SampledWavelengths::sample();
+        if camera.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+        spectrum::scoped(wavelengths.hero(), || camera.get_ray(u, v).map_or(Vec3::zero_vector(), |r| color(&r, &*bvh_tree, environment.as_ref(), camera_medium, max_depth, rr_depth, Some(wavelengths))))
+    } else {
+        camera.get_ray(u, v).map_or(Vec3::zero_vector(), |r| color(&r, &*bvh_tree, environment.as_ref(), camera_medium, max_depth, rr_depth, None))
+    };
 
-            let mut avg_color = Vec3::zero_vector();
+    let splat_scale = match integrator {
+        "photon" => {
+            photon::render(&*camera, &*bvh_tree, &lights, &mut film, samples_per_pixel, &photon_settings);
+            0.0
+        },
+        //Every sppm iteration takes one sample per pixel
+        "sppm" => {
+            photon::render_progressive(&*camera, &*bvh_tree, &lights, &mut film, samples_per_pixel, &photon_settings);
+            0.0
+        },
+        //Samples per pixel are the average number of mutations per pixel
+        "mlt" => mlt::render(&film, samples_per_pixel, &mlt_settings, trace),
+        _ => {
+            for y in (0..image_height).rev() {
+                for x in 0..image_width {
+                    let mut samples = vec![Vec3::zero_vector(); samples_per_pixel];
 
-            samples.iter().for_each( |sample| {
-                avg_color = avg_color + *sample;
-            });
+                    samples.par_iter_mut().for_each( |sample| {
+                        let u = (x as f32 + sampler::random()) / image_width as f32;
+                        let v = (y as f32 + sampler::random()) / image_height as f32;
 
-            avg_color = avg_color / samples_per_pixel as f32;
+                        *sample = match integrator {
+                            "bdpt" => bdpt::color(&*camera, &*bvh_tree, &lights, &film, u, v, max_depth),
+                            _ => trace(u, v)
+                        };
+                    });
 
-            //Do gamma correction
-            avg_color = avg_color.clamp(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
-            avg_color = Vec3::new(avg_color.x().sqrt(), avg_color.y().sqrt(), avg_color.z().sqrt());
+                    let mut avg_color = Vec3::zero_vector();
 
-            let ir = (255.99*avg_color.x()) as u8;
-            let ig = (255.99*avg_color.y()) as u8;
-            let ib = (255.99*avg_color.z()) as u8;
+                    samples.iter().for_each( |sample| {
+                        avg_color = avg_color + *sample;
+                    });
 
-            data.push(ir);
-            data.push(ig);
-            data.push(ib);
-            data.push(255);
+                    film.set_pixel(x, y, avg_color / samples_per_pixel as f32);
+                }
+                print!("{} / {} scanlines rendered \r", (image_height - y), image_height)
+            }
+            1.0 / samples_per_pixel as f32
         }
-        print!("{} / {} scanlines rendered \r", (image_height - y), image_height)
-    }
+    };
 
     //Save end time
     let end_time = std::time::Instant::now();
@@ -217,18 +1088,21 @@ fn main() {
 
     println!("Render took {}.{} seconds", render_time_sec, render_time_ms);
 
-    //Store image to file
-    let path = Path::new(output_filename);
-    let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
-
-    let mut encoder = png::Encoder::new(w, image_width, image_height);
-    encoder.set_color(png::ColorType::RGBA);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-
+    film.vignette(|s, t| camera.vignetting(s, t));
 
-    writer.write_image_data(&data).unwrap();
+    //Store image to file
+    if camera_kind == "cubemap" && cubemap_layout == CubemapLayout::Faces {
+        let output = Path::new(output_filename);
+        let stem = output.file_stem().unwrap().to_string_lossy();
+        let extension = output.extension().map_or("png".into(), |extension| extension.to_string_lossy());
+        let face_width = image_width / 6;
+        for (face, name) in CUBE_FACES.iter().enumerate() {
+            let filename = output.with_file_name(format!("{}_{}.{}", stem, name, extension));
+            film.crop(face as u32 * face_width, 0, face_width, image_height).write_png(&filename.to_string_lossy(), splat_scale);
+        }
+    } else {
+        film.write_png(output_filename, splat_scale);
+    }
 
     println!("Done");
 }
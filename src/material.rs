@@ -1,17 +1,86 @@
 use ray::Ray;
 use vec3::Vec3;
-use texture::Texture;
+use texture::{Texture, TexCoord};
+use sampler;
+use microfacet::{Frame, TrowbridgeReitz, fresnel_conductor, fresnel_dielectric};
+use microfacet;
+use spectrum::Dispersion;
 
-extern crate rand;
+//Volume enclosed by a dielectric, tracked by the path integrator to nest dielectrics. Where
+//volumes overlap the one with the highest priority is considered inside.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Interior {
+    pub ior: f32,
+    pub absorption: Vec3,
+    pub priority: u32
+}
 
 pub struct ScatterRecord {
     pub attenuation: Vec3,
-    pub scattered: Ray
+    pub scattered: Ray,
+    pub is_specular: bool
 }
 
+//wo and wi are unit vectors pointing away from the surface, wo towards the viewer and wi towards the light
 pub trait Material {
-    fn scatter(&self, r: &Ray, t: f32, point: Vec3, normal: Vec3) -> Option<ScatterRecord>;
+    fn scatter(&self, r: &Ray, t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord>;
     fn emitted(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+    //BSDF value, specular materials return zero as they can only be sampled through scatter
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _point: Vec3, _normal: Vec3, _uv: TexCoord) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    //Solid angle density with which scatter chooses wi given wo
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _point: Vec3, _normal: Vec3, _uv: TexCoord) -> f32 {
+        0.0
+    }
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    //Dielectrics enclosing a volume describe it for nested dielectric tracking
+    fn interior(&self) -> Option<Interior> {
+        None
+    }
+    //Scatters at an interface between two volumes, eta is the index of refraction on the far side
+    //of the surface relative to the side the ray arrives from
+    fn scatter_between(&self, r: &Ray, t: f32, point: Vec3, normal: Vec3, uv: TexCoord, _eta: f32) -> Option<ScatterRecord> {
+        self.scatter(r, t, point, normal, uv)
+    }
+    //Scattering depends on the hero wavelength of spectral paths
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+}
+
+impl Interior {
+    //Beer-Lambert law
+    pub fn transmittance(&self, distance: f32) -> Vec3 {
+        (-distance * self.absorption).exp()
+    }
+}
+
+//Absorption coefficient that leaves color after traveling distance
+fn absorption_for(color: Vec3, distance: f32) -> Vec3 {
+    let channel = |c: f32| -c.clamp(0.000_001, 1.0).ln() / distance;
+    Vec3::new(channel(color.x()), channel(color.y()), channel(color.z()))
+}
+
+//Normal on the side the ray arrives from
+fn facing_normal(r: &Ray, normal: Vec3) -> Vec3 {
+    if r.direction().dot(normal) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+//Shading normal turned to the outside of the primitive. Hits face their normal towards the ray,
+//only the geometric normal in the texture coordinates tells which side the ray came from.
+pub fn outward_normal(normal: Vec3, uv: &TexCoord) -> Vec3 {
+    if normal.dot(uv.normal) < 0.0 {
+        -normal
+    } else {
+        normal
+    }
 }
 
 pub struct Lambertian {
@@ -23,14 +92,39 @@ pub struct Metal {
     fuzz: f32
 }
 
+//Rough metal with a GGX microfacet distribution and the Fresnel reflectance of a complex index
+//of refraction eta + ik. Anisotropy follows the arbitrary tangent frame of the normal.
+pub struct Conductor {
+    distribution: TrowbridgeReitz,
+    eta: Vec3,
+    k: Vec3
+}
+
+//Smooth glass. Absorption and priority take effect where the path integrator tracks nested
+//dielectrics, other integrators refract against vacuum and ignore them.
 pub struct Dielectric {
-    ref_idx: f32
+    ior: Dispersion,
+    absorption: Vec3,
+    priority: u32
+}
+
+//Frosted glass, GGX microfacet reflection and transmission (Walter et al. 2007) with exact
+//Fresnel. The normal points to the outside, eta is the inside index of refraction. As with
+//Dielectric, transmitted radiance is not scaled by the squared ratio of indices.
+pub struct RoughDielectric {
+    eta: Dispersion,
+    roughness: Box<dyn Texture + Sync>,
+    absorption: Vec3,
+    priority: u32
 }
 
 pub struct Isotropic {
     albedo: Box<dyn Texture + Sync>
 }
 
+//Invisible surface, used for boundaries that only separate participating media
+pub struct Interface;
+
 pub struct DiffuseLight {
     emit: Box<dyn Texture + Sync>
 }
@@ -52,14 +146,113 @@ impl Metal {
     }
 }
 
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f32, anisotropy: f32) -> Conductor {
+        Conductor {
+            distribution: TrowbridgeReitz::from_roughness(roughness, anisotropy),
+            eta,
+            k
+        }
+    }
+
+    //Measured indices of refraction at 650, 550 and 450nm
+    pub fn gold(roughness: f32, anisotropy: f32) -> Conductor {
+        Conductor::new(Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603), roughness, anisotropy)
+    }
+
+    pub fn silver(roughness: f32, anisotropy: f32) -> Conductor {
+        Conductor::new(Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147), roughness, anisotropy)
+    }
+
+    pub fn copper(roughness: f32, anisotropy: f32) -> Conductor {
+        Conductor::new(Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142), roughness, anisotropy)
+    }
+
+    pub fn aluminium(roughness: f32, anisotropy: f32) -> Conductor {
+        Conductor::new(Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837), roughness, anisotropy)
+    }
+
+    //Shading frame on the side of the surface wo lies on, conductors are two sided
+    fn frame(wo: Vec3, normal: Vec3) -> Frame {
+        if wo.dot(normal) < 0.0 {
+            Frame::new(-normal)
+        } else {
+            Frame::new(normal)
+        }
+    }
+}
+
 impl Dielectric {
     pub fn new(ref_idx: f32) -> Dielectric {
         Dielectric {
-            ref_idx
+            ior: Dispersion::Constant(ref_idx),
+            absorption: Vec3::zero_vector(),
+            priority: 0
+        }
+    }
+
+    //Tints the interior so that light keeps color after traveling distance through it
+    pub fn with_absorption(self, color: Vec3, distance: f32) -> Dielectric {
+        Dielectric {
+            absorption: absorption_for(color, distance),
+            ..self
+        }
+    }
+
+    //Where dielectric volumes overlap the one with the highest priority is inside
+    pub fn with_priority(self, priority: u32) -> Dielectric {
+        Dielectric {
+            priority,
+            ..self
+        }
+    }
+
+    //Wavelength dependent index of refraction, only spectral rendering disperses light
+    pub fn with_dispersion(self, ior: Dispersion) -> Dielectric {
+        Dielectric {
+            ior,
+            ..self
         }
     }
 }
 
+impl RoughDielectric {
+    //The roughness texture is read from its first channel
+    pub fn new(eta: f32, roughness: Box<dyn Texture + Sync>) -> RoughDielectric {
+        RoughDielectric {
+            eta: Dispersion::Constant(eta),
+            roughness,
+            absorption: Vec3::zero_vector(),
+            priority: 0
+        }
+    }
+
+    pub fn with_absorption(self, color: Vec3, distance: f32) -> RoughDielectric {
+        RoughDielectric {
+            absorption: absorption_for(color, distance),
+            ..self
+        }
+    }
+
+    pub fn with_priority(self, priority: u32) -> RoughDielectric {
+        RoughDielectric {
+            priority,
+            ..self
+        }
+    }
+
+    pub fn with_dispersion(self, eta: Dispersion) -> RoughDielectric {
+        RoughDielectric {
+            eta,
+            ..self
+        }
+    }
+
+    fn distribution(&self, point: Vec3, uv: TexCoord) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.filtered(&uv, &point).x(), 0.0)
+    }
+}
+
 impl Isotropic {
     pub fn new(albedo: Box<dyn Texture + Sync>) -> Isotropic {
         Isotropic {
@@ -78,13 +271,32 @@ impl DiffuseLight {
 
 fn random_in_unit_sphere() -> Vec3 {
     loop {
-        let p = 2.0 * Vec3::new(rand::random::<f32>(), rand::random::<f32>(), rand::random::<f32>()) - Vec3::new(1.0, 1.0, 1.0);
+        let p = 2.0 * Vec3::new(sampler::random(), sampler::random(), sampler::random()) - Vec3::new(1.0, 1.0, 1.0);
         if p.squared_length() < 1.0 {
             return p;
         }
     }
 }
 
+pub fn random_unit_vector() -> Vec3 {
+    loop {
+        let p = random_in_unit_sphere();
+        if p.squared_length() > 0.0001 {
+            return Vec3::unit_vector(p);
+        }
+    }
+}
+
+//Cosine weighted direction around normal, with density cos(theta) / pi
+pub fn random_cosine_direction(normal: Vec3) -> Vec3 {
+    loop {
+        let d = normal + random_unit_vector();
+        if d.squared_length() > 0.0001 {
+            return Vec3::unit_vector(d);
+        }
+    }
+}
+
 fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - (2.0 * v.dot(n) * n)
 }
@@ -118,27 +330,38 @@ fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> RefractRecord {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r: &Ray, _t: f32, point: Vec3, normal: Vec3) -> Option<ScatterRecord> {
-        let target = point + normal + random_in_unit_sphere();
+    fn scatter(&self, _r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            attenuation: self.albedo.value(0.0, 0.0, &point),
-            scattered: Ray::new(point, target - point)
+            attenuation: self.albedo.filtered(&uv, &point),
+            scattered: Ray::new(point, random_cosine_direction(normal)),
+            is_specular: false
         })
     }
     fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+    fn eval(&self, _wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> Vec3 {
+        if wi.dot(normal) > 0.0 {
+            self.albedo.filtered(&uv, &point) / std::f32::consts::PI
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+    fn pdf(&self, _wo: Vec3, wi: Vec3, _point: Vec3, normal: Vec3, _uv: TexCoord) -> f32 {
+        wi.dot(normal).max(0.0) / std::f32::consts::PI
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3) -> Option<ScatterRecord> {
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
         let reflected = reflect(Vec3::unit_vector(r.direction()), normal);
 
         let scattered = Ray::new(point, reflected + self.fuzz*random_in_unit_sphere());
         if scattered.direction().dot(normal) > 0.0 {
             Some(ScatterRecord {
-                attenuation: self.albedo.value(0.0, 0.0, &point),
-                scattered
+                attenuation: self.albedo.filtered(&uv, &point),
+                scattered,
+                is_specular: true
             })
         } else {
             None
@@ -149,64 +372,221 @@ impl Material for Metal {
     }
 }
 
+impl Material for Conductor {
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, _uv: TexCoord) -> Option<ScatterRecord> {
+        let wo = -Vec3::unit_vector(r.direction());
+        let frame = Conductor::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+
+        if self.distribution.is_smooth() {
+            return Some(ScatterRecord {
+                attenuation: fresnel_conductor(wo_local.z(), self.eta, self.k),
+                scattered: Ray::new(point, frame.to_world(microfacet::reflect(wo_local, Vec3::new(0.0, 0.0, 1.0)))),
+                is_specular: true
+            });
+        }
+
+        let wh = self.distribution.sample_wh(wo_local, sampler::random(), sampler::random());
+        let wi_local = microfacet::reflect(wo_local, wh);
+        if wi_local.z() <= 0.0 {
+            return None;
+        }
+        //f * cos / pdf simplifies to F * G / G1 for visible normal sampling
+        let fresnel = fresnel_conductor(wo_local.dot(wh), self.eta, self.k);
+        Some(ScatterRecord {
+            attenuation: fresnel * (self.distribution.g(wo_local, wi_local) / self.distribution.g1(wo_local)),
+            scattered: Ray::new(point, frame.to_world(wi_local)),
+            is_specular: false
+        })
+    }
+    fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    fn eval(&self, wo: Vec3, wi: Vec3, _point: Vec3, normal: Vec3, _uv: TexCoord) -> Vec3 {
+        let frame = Conductor::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let wi_local = frame.to_local(wi);
+        if wo_local.z() <= 0.0 || wi_local.z() <= 0.0 {
+            return Vec3::zero_vector();
+        }
+        let wh = Vec3::unit_vector(wo_local + wi_local);
+        let fresnel = fresnel_conductor(wo_local.dot(wh), self.eta, self.k);
+        fresnel * (self.distribution.d(wh) * self.distribution.g(wo_local, wi_local) / (4.0 * wo_local.z() * wi_local.z()))
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3, _point: Vec3, normal: Vec3, _uv: TexCoord) -> f32 {
+        let frame = Conductor::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let wi_local = frame.to_local(wi);
+        if wo_local.z() <= 0.0 || wi_local.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = Vec3::unit_vector(wo_local + wi_local);
+        self.distribution.pdf(wo_local, wh) / (4.0 * wo_local.dot(wh))
+    }
+}
+
 impl Material for Dielectric {
-    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3) -> Option<ScatterRecord> {
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
+        let normal = outward_normal(normal, &uv);
+        let ref_idx = self.ior.current_ior();
         let outward_normal: Vec3;
         let reflected = reflect(r.direction(), normal);
         let ni_over_nt: f32;
         let cosine: f32;
-        let reflect_prob: f32;
-        let scattered: Ray;
 
         if r.direction().dot(normal) > 0.0 {
-            outward_normal = -1.0 * normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * r.direction().dot(normal) / r.direction().length();
+            outward_normal = -normal;
+            ni_over_nt = ref_idx;
+            cosine = ref_idx * r.direction().dot(normal) / r.direction().length();
         } else {
             outward_normal = normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -1.0 * r.direction().dot(normal) / r.direction().length();
+            ni_over_nt = 1.0 / ref_idx;
+            cosine = -r.direction().dot(normal) / r.direction().length();
         }
         let refract_rec = refract(r.direction(), outward_normal, ni_over_nt);
-        if refract_rec.should_refract {
-            reflect_prob = schlick(cosine, self.ref_idx);
+        let reflect_prob = if refract_rec.should_refract {
+            schlick(cosine, ref_idx)
         } else {
-            reflect_prob = 1.0;
-        }
+            1.0
+        };
 
-        if rand::random::<f32>() < reflect_prob {
-            scattered = Ray::new(point, reflected);
+        let scattered = if sampler::random() < reflect_prob {
+            Ray::new(point, reflected)
         } else {
-            scattered = Ray::new(point, refract_rec.refracted);
-        }
+            Ray::new(point, refract_rec.refracted)
+        };
 
         Some(ScatterRecord {
             attenuation: Vec3::new(1.0, 1.0, 1.0),
-            scattered
+            scattered,
+            is_specular: true
+        })
+    }
+    fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            ior: self.ior.current_ior(),
+            absorption: self.absorption,
+            priority: self.priority
+        })
+    }
+    fn scatter_between(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, _uv: TexCoord, eta: f32) -> Option<ScatterRecord> {
+        let wo = -Vec3::unit_vector(r.direction());
+        let normal = facing_normal(r, normal);
+        let reflectance = fresnel_dielectric(wo.dot(normal), eta);
+        let direction = match microfacet::refract(wo, normal, eta) {
+            Some(refracted) if sampler::random() >= reflectance => refracted,
+            _ => microfacet::reflect(wo, normal)
+        };
+        Some(ScatterRecord {
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            scattered: Ray::new(point, direction),
+            is_specular: true
+        })
+    }
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
+        let frame = Frame::new(outward_normal(normal, &uv));
+        let wo = frame.to_local(-Vec3::unit_vector(r.direction()));
+        let distribution = self.distribution(point, uv);
+        let (wi, weight) = distribution.sample_dielectric(wo, self.eta.current_ior(), sampler::random(), sampler::random(), sampler::random())?;
+        Some(ScatterRecord {
+            attenuation: Vec3::new(weight, weight, weight),
+            scattered: Ray::new(point, frame.to_world(wi)),
+            is_specular: distribution.is_smooth()
         })
     }
     fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+    fn eval(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> Vec3 {
+        let distribution = self.distribution(point, uv);
+        if distribution.is_smooth() {
+            return Vec3::zero_vector();
+        }
+        let frame = Frame::new(outward_normal(normal, &uv));
+        let (reflected, transmitted) = distribution.eval_dielectric(frame.to_local(wo), frame.to_local(wi), self.eta.current_ior());
+        let f = reflected + transmitted;
+        Vec3::new(f, f, f)
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> f32 {
+        let distribution = self.distribution(point, uv);
+        if distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = Frame::new(outward_normal(normal, &uv));
+        distribution.pdf_dielectric(frame.to_local(wo), frame.to_local(wi), self.eta.current_ior())
+    }
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            ior: self.eta.current_ior(),
+            absorption: self.absorption,
+            priority: self.priority
+        })
+    }
+    fn scatter_between(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord, eta: f32) -> Option<ScatterRecord> {
+        let frame = Frame::new(facing_normal(r, normal));
+        let wo = frame.to_local(-Vec3::unit_vector(r.direction()));
+        let distribution = self.distribution(point, uv);
+        let (wi, weight) = distribution.sample_dielectric(wo, eta, sampler::random(), sampler::random(), sampler::random())?;
+        Some(ScatterRecord {
+            attenuation: Vec3::new(weight, weight, weight),
+            scattered: Ray::new(point, frame.to_world(wi)),
+            is_specular: distribution.is_smooth()
+        })
+    }
+    fn is_dispersive(&self) -> bool {
+        self.eta.is_dispersive()
+    }
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, _r: &Ray, _t: f32, point: Vec3, _normal: Vec3) -> Option<ScatterRecord> {
+    fn scatter(&self, _r: &Ray, _t: f32, point: Vec3, _normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            attenuation: self.albedo.value(0.0, 0.0, &point),
-            scattered: Ray::new(point, random_in_unit_sphere())
+            attenuation: self.albedo.filtered(&uv, &point),
+            scattered: Ray::new(point, random_unit_vector()),
+            is_specular: false
         })
     }
     fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+    fn eval(&self, _wo: Vec3, _wi: Vec3, point: Vec3, _normal: Vec3, uv: TexCoord) -> Vec3 {
+        self.albedo.filtered(&uv, &point) / (4.0 * std::f32::consts::PI)
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _point: Vec3, _normal: Vec3, _uv: TexCoord) -> f32 {
+        1.0 / (4.0 * std::f32::consts::PI)
+    }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _r: &Ray, _t: f32, _point: Vec3, _normal: Vec3) -> Option<ScatterRecord> {
+    fn scatter(&self, _r: &Ray, _t: f32, _point: Vec3, _normal: Vec3, _uv: TexCoord) -> Option<ScatterRecord> {
         None
     }
     fn emitted(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
         self.emit.value(u, v, p)
     }
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+impl Material for Interface {
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, _normal: Vec3, _uv: TexCoord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            scattered: Ray::new(point, r.direction()),
+            is_specular: true
+        })
+    }
+    fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
 }
@@ -0,0 +1,280 @@
+use vec3::Vec3;
+use ray::Ray;
+use aabb::AABB;
+use sampler;
+use std::sync::Arc;
+
+//Directions follow the surface convention: wo points back towards where the path came from
+pub trait PhaseFunction {
+    fn p(&self, wo: Vec3, wi: Vec3) -> f32;
+    //Sampling is exact, the returned direction has density p(wo, wi)
+    fn sample(&self, wo: Vec3) -> Vec3;
+}
+
+//Result of free flight sampling along a ray segment. When t is set the ray scattered at that
+//parameter, weight always multiplies the path throughput. emission is the radiance emitted by
+//the medium along the segment, relative to the throughput at the start of the ray.
+pub struct MediumSample {
+    pub t: Option<f32>,
+    pub weight: Vec3,
+    pub emission: Vec3
+}
+
+pub trait Medium {
+    fn sample(&self, r: &Ray, t_max: f32) -> MediumSample;
+    fn phase(&self) -> &dyn PhaseFunction;
+}
+
+//Media on both sides of a surface, inside is the side opposite to the outward facing normal
+#[derive(Clone)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<dyn Medium + Send + Sync>>,
+    pub outside: Option<Arc<dyn Medium + Send + Sync>>
+}
+
+pub struct HenyeyGreenstein {
+    g: f32
+}
+
+pub struct HomogeneousMedium {
+    sigma_a: Vec3,
+    sigma_s: Vec3,
+    phase: HenyeyGreenstein
+}
+
+pub trait DensityField {
+    fn density(&self, p: Vec3) -> f32;
+    fn max_density(&self) -> f32;
+    //Region outside of which the density is zero
+    fn bounds(&self) -> AABB;
+    //Radiance emitted per unit of absorption
+    fn emission(&self, _p: Vec3) -> Vec3 {
+        Vec3::zero_vector()
+    }
+    //Visits consecutive ray segments (t_start, t_end, max density) covering all density along the
+    //ray up to t_max, stops early when visit returns false. Tighter bounds make tracking cheaper.
+    fn majorants(&self, r: &Ray, t_max: f32, visit: &mut dyn FnMut(f32, f32, f32) -> bool) {
+        if let Some((t_start, t_end)) = self.bounds().intersect(r, 0.0, t_max) {
+            visit(t_start, t_end, self.max_density());
+        }
+    }
+}
+
+//Density scales the absorption and scattering coefficients, which may differ per channel
+pub struct HeterogeneousMedium<D: DensityField> {
+    density: D,
+    sigma_a: Vec3,
+    sigma_s: Vec3,
+    phase: HenyeyGreenstein
+}
+
+//Ground fog falling off exponentially with height above base_height
+pub struct HeightFog {
+    base_density: f32,
+    base_height: f32,
+    falloff: f32,
+    bounds: AABB
+}
+
+fn average(v: Vec3) -> f32 {
+    (v.x() + v.y() + v.z()) / 3.0
+}
+
+fn channel(v: Vec3, index: usize) -> f32 {
+    match index {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z()
+    }
+}
+
+impl MediumInterface {
+    pub fn new(inside: Option<Arc<dyn Medium + Send + Sync>>, outside: Option<Arc<dyn Medium + Send + Sync>>) -> MediumInterface {
+        MediumInterface {
+            inside,
+            outside
+        }
+    }
+}
+
+impl HenyeyGreenstein {
+    //g in (-1, 1), positive values scatter forward, zero is isotropic
+    pub fn new(g: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99)
+        }
+    }
+}
+
+fn phase_hg(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.max(0.0).sqrt())
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, wo: Vec3, wi: Vec3) -> f32 {
+        phase_hg(wo.dot(wi), self.g)
+    }
+
+    fn sample(&self, wo: Vec3) -> Vec3 {
+        let u1 = sampler::random();
+        let u2 = sampler::random();
+        let g = self.g;
+        let cos_theta = if g.abs() < 0.001 {
+            1.0 - 2.0 * u1
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+            -(1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let (v1, v2) = wo.coordinate_system();
+        sin_theta * phi.cos() * v1 + sin_theta * phi.sin() * v2 + cos_theta * wo
+    }
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Vec3, sigma_s: Vec3, g: f32) -> HomogeneousMedium {
+        HomogeneousMedium {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g)
+        }
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    //Distances are sampled in a randomly chosen channel, weights use the density averaged over channels
+    fn sample(&self, r: &Ray, t_max: f32) -> MediumSample {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let length = r.direction().length();
+        let sigma = channel(sigma_t, ((sampler::random() * 3.0) as usize).min(2));
+        let dist = -(1.0 - sampler::random()).ln() / sigma;
+        let t = (dist / length).min(t_max);
+        let scattered = t < t_max;
+
+        let tr = (-t * length * sigma_t).exp();
+        let density = if scattered { sigma_t * tr } else { tr };
+        let pdf = average(density);
+        if pdf <= 0.0 {
+            return MediumSample {
+                t: None,
+                weight: Vec3::zero_vector(),
+                emission: Vec3::zero_vector()
+            };
+        }
+        if scattered {
+            MediumSample {
+                t: Some(t),
+                weight: tr * self.sigma_s / pdf,
+                emission: Vec3::zero_vector()
+            }
+        } else {
+            MediumSample {
+                t: None,
+                weight: tr / pdf,
+                emission: Vec3::zero_vector()
+            }
+        }
+    }
+
+    fn phase(&self) -> &dyn PhaseFunction {
+        &self.phase
+    }
+}
+
+impl<D: DensityField> HeterogeneousMedium<D> {
+    pub fn new(density: D, sigma_a: Vec3, sigma_s: Vec3, g: f32) -> HeterogeneousMedium<D> {
+        HeterogeneousMedium {
+            density,
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g)
+        }
+    }
+}
+
+impl<D: DensityField> Medium for HeterogeneousMedium<D> {
+    //Delta tracking against per segment majorants shared by all channels. Tentative collisions
+    //become real scattering or null collisions with probabilities proportional to the largest
+    //channel, the per channel mismatch and absorption go into the weight. Emission is collected
+    //at every tentative collision.
+    fn sample(&self, r: &Ray, t_max: f32) -> MediumSample {
+        let mut weight = Vec3::new(1.0, 1.0, 1.0);
+        let mut emission = Vec3::zero_vector();
+        let mut scatter = None;
+        let length = r.direction().length();
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let max_sigma_t = sigma_t.max_component();
+
+        self.density.majorants(r, t_max, &mut |t_start, t_end, max_density| {
+            let majorant = max_density * max_sigma_t;
+            if majorant <= 0.0 {
+                return true;
+            }
+            let mut t = t_start;
+            loop {
+                t -= (1.0 - sampler::random()).ln() / (majorant * length);
+                if t >= t_end {
+                    return true;
+                }
+                let p = r.point_at_parameter(t);
+                let density = self.density.density(p);
+                emission = emission + weight * (density / majorant) * self.sigma_a * self.density.emission(p);
+
+                let sigma_s = density * self.sigma_s;
+                let sigma_n = Vec3::new(majorant, majorant, majorant) - density * sigma_t;
+                let p_scatter = sigma_s.max_component();
+                let p_null = sigma_n.max_component().max(0.0);
+                if p_scatter + p_null <= 0.0 {
+                    weight = Vec3::zero_vector();
+                    return false;
+                }
+                if sampler::random() * (p_scatter + p_null) < p_scatter {
+                    weight = weight * sigma_s * ((p_scatter + p_null) / (majorant * p_scatter));
+                    scatter = Some(t);
+                    return false;
+                }
+                weight = weight * sigma_n * ((p_scatter + p_null) / (majorant * p_null));
+                if weight.max_component() <= 0.0 {
+                    return false;
+                }
+            }
+        });
+
+        MediumSample {
+            t: scatter,
+            weight,
+            emission
+        }
+    }
+
+    fn phase(&self) -> &dyn PhaseFunction {
+        &self.phase
+    }
+}
+
+impl HeightFog {
+    pub fn new(base_density: f32, base_height: f32, falloff: f32, bounds: AABB) -> HeightFog {
+        HeightFog {
+            base_density,
+            base_height,
+            falloff,
+            bounds
+        }
+    }
+}
+
+impl DensityField for HeightFog {
+    fn density(&self, p: Vec3) -> f32 {
+        self.base_density * (-self.falloff * (p.y() - self.base_height).max(0.0)).exp()
+    }
+
+    fn max_density(&self) -> f32 {
+        self.base_density
+    }
+
+    fn bounds(&self) -> AABB {
+        self.bounds
+    }
+}
@@ -0,0 +1,277 @@
+use vec3::Vec3;
+
+//Orthonormal shading frame, local directions have the normal along z
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3
+}
+
+//Trowbridge-Reitz (GGX) microfacet distribution with roughness alpha_x and alpha_y along the
+//tangent axes of the shading frame. All directions are local and point away from the surface.
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32
+}
+
+impl Frame {
+    pub fn new(normal: Vec3) -> Frame {
+        let (s, t) = normal.coordinate_system();
+        Frame {
+            s,
+            t,
+            n: normal
+        }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.s + v.y() * self.t + v.z() * self.n
+    }
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> TrowbridgeReitz {
+        TrowbridgeReitz {
+            alpha_x: alpha_x.max(0.0001),
+            alpha_y: alpha_y.max(0.0001)
+        }
+    }
+
+    //Perceptual roughness and anisotropy in [0, 1], mapped as in the Disney BRDF
+    pub fn from_roughness(roughness: f32, anisotropy: f32) -> TrowbridgeReitz {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        TrowbridgeReitz::new(alpha / aspect, alpha * aspect)
+    }
+
+    //Below this roughness the distribution is treated as a perfect mirror
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 0.001
+    }
+
+    pub fn d(&self, wh: Vec3) -> f32 {
+        if wh.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wh.x() / self.alpha_x;
+        let y = wh.y() / self.alpha_y;
+        let e = x * x + y * y + wh.z() * wh.z();
+        1.0 / (std::f32::consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let x = w.x() * self.alpha_x;
+        let y = w.y() * self.alpha_y;
+        let alpha2_tan2 = (x * x + y * y) / cos2;
+        (-1.0 + (1.0 + alpha2_tan2).sqrt()) / 2.0
+    }
+
+    //Smith masking
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    //Height correlated Smith masking and shadowing
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    //Density of the normals visible from wo, the density sample_wh draws from
+    pub fn pdf(&self, wo: Vec3, wh: Vec3) -> f32 {
+        if wo.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(wh).abs() * self.d(wh) / wo.z().abs()
+    }
+
+    //Visible normal sampling (Heitz 2018), wh ends up on the same side as wo
+    pub fn sample_wh(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let flip = wo.z() < 0.0;
+        let wo = if flip { -wo } else { wo };
+
+        //Stretch to the hemisphere configuration
+        let vh = Vec3::unit_vector(Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()));
+        let length_squared = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        //Uniform disk sample warped towards the visible part of the hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        let wh = Vec3::unit_vector(Vec3::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(0.000_001)));
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+
+    //Half vector on the upper side for a reflected or refracted pair of directions, together with
+    //the relative index of refraction across the interface. eta is the index below the surface
+    //relative to above it, as for the functions below.
+    fn dielectric_half_vector(&self, wo: Vec3, wi: Vec3, eta: f32) -> Option<(Vec3, f32)> {
+        let reflect = wo.z() * wi.z() > 0.0;
+        let etap = if reflect {
+            1.0
+        } else if wo.z() > 0.0 {
+            eta
+        } else {
+            1.0 / eta
+        };
+        let wh = wi * etap + wo;
+        if wo.z() == 0.0 || wi.z() == 0.0 || wh.squared_length() == 0.0 {
+            return None;
+        }
+        let wh = Vec3::unit_vector(wh);
+        let wh = if wh.z() < 0.0 { -wh } else { wh };
+        //Microfacets seen from behind do not contribute
+        if wh.dot(wi) * wi.z() < 0.0 || wh.dot(wo) * wo.z() < 0.0 {
+            return None;
+        }
+        Some((wh, etap))
+    }
+
+    //Rough dielectric BSDF (Walter et al. 2007) split into its reflected and transmitted parts,
+    //one of which is zero. Transmitted radiance is not scaled by the squared relative index.
+    pub fn eval_dielectric(&self, wo: Vec3, wi: Vec3, eta: f32) -> (f32, f32) {
+        let (wh, etap) = match self.dielectric_half_vector(wo, wi, eta) {
+            Some(half) => half,
+            None => return (0.0, 0.0)
+        };
+        let reflectance = fresnel_dielectric(wo.dot(wh), eta);
+        if wo.z() * wi.z() > 0.0 {
+            (reflectance * self.d(wh) * self.g(wo, wi) / (4.0 * wo.z() * wi.z()).abs(), 0.0)
+        } else {
+            let denom = (wi.dot(wh) + wo.dot(wh) / etap).powi(2) * wi.z() * wo.z();
+            (0.0, (1.0 - reflectance) * self.d(wh) * self.g(wo, wi) * (wi.dot(wh) * wo.dot(wh) / denom).abs())
+        }
+    }
+
+    //Density of sample_dielectric, reflection and transmission are chosen by their Fresnel weights
+    pub fn pdf_dielectric(&self, wo: Vec3, wi: Vec3, eta: f32) -> f32 {
+        let (wh, etap) = match self.dielectric_half_vector(wo, wi, eta) {
+            Some(half) => half,
+            None => return 0.0
+        };
+        let reflectance = fresnel_dielectric(wo.dot(wh), eta);
+        if wo.z() * wi.z() > 0.0 {
+            self.pdf(wo, wh) / (4.0 * wo.dot(wh).abs()) * reflectance
+        } else {
+            let denom = (wi.dot(wh) + wo.dot(wh) / etap).powi(2);
+            self.pdf(wo, wh) * wi.dot(wh).abs() / denom * (1.0 - reflectance)
+        }
+    }
+
+    //Samples a reflected or refracted direction and returns it with f * cos / pdf. Smooth
+    //distributions reflect or refract around the normal with a weight of one.
+    pub fn sample_dielectric(&self, wo: Vec3, eta: f32, u1: f32, u2: f32, u3: f32) -> Option<(Vec3, f32)> {
+        let wh = if self.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            let wh = self.sample_wh(wo, u1, u2);
+            if wh.z() < 0.0 { -wh } else { wh }
+        };
+
+        //The Fresnel weights of the choice cancel out
+        let reflectance = fresnel_dielectric(wo.dot(wh), eta);
+        let wi = if u3 < reflectance {
+            reflect(wo, wh)
+        } else {
+            refract(wo, wh, eta)?
+        };
+        if self.is_smooth() {
+            return Some((wi, 1.0));
+        }
+        //Reflected directions must stay on the side of wo, transmitted ones must cross
+        let reflected = wh.dot(wi) * wh.dot(wo) > 0.0;
+        if (wo.z() * wi.z() > 0.0) != reflected {
+            return None;
+        }
+        Some((wi, self.g(wo, wi) / self.g1(wo)))
+    }
+}
+
+pub fn reflect(wo: Vec3, n: Vec3) -> Vec3 {
+    -wo + 2.0 * wo.dot(n) * n
+}
+
+fn fresnel_conductor_channel(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+//Reflectance of a conductor with complex index of refraction eta + ik relative to the outside
+pub fn fresnel_conductor(cos_theta_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    Vec3::new(fresnel_conductor_channel(cos_theta_i, eta.x(), k.x()),
+              fresnel_conductor_channel(cos_theta_i, eta.y(), k.y()),
+              fresnel_conductor_channel(cos_theta_i, eta.z(), k.z()))
+}
+
+//Refracts wo through a surface with normal n, eta is the index of refraction on the side opposite
+//to n relative to the side of n. Returns None on total internal reflection.
+pub fn refract(wo: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let mut cos_theta_i = n.dot(wo);
+    let (eta, n) = if cos_theta_i < 0.0 {
+        cos_theta_i = -cos_theta_i;
+        (1.0 / eta, -n)
+    } else {
+        (eta, n)
+    };
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wo / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+//Exact reflectance of a dielectric interface for unpolarized light. cos_theta_i is measured
+//against the normal on the outside, eta is the inside index of refraction relative to the outside.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).max(0.0).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
@@ -0,0 +1,217 @@
+use vec3::Vec3;
+use film::Film;
+use sampler;
+use sampler::Sampler;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+extern crate rand;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+extern crate rayon;
+use rayon::prelude::*;
+
+//Primary sample space Metropolis light transport (Kelemen et al. 2002). A path is a deterministic
+//function of the sequence of random numbers drawn through sampler::random(), Markov chains explore
+//that space with small perturbations and occasional independent large steps.
+
+pub struct MltSettings {
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub large_step_probability: f32,
+    pub sigma: f32
+}
+
+#[derive(Clone, Copy)]
+struct PrimarySample {
+    value: f32,
+    last_modification_iteration: u64,
+    value_backup: f32,
+    modify_backup: u64
+}
+
+//Samples are mutated lazily when they are requested, so paths of any length can be replayed
+struct PrimarySampleSpace {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    index: usize,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+    large_step_probability: f32,
+    sigma: f32
+}
+
+impl PrimarySampleSpace {
+    fn new(seed: u64, settings: &MltSettings) -> PrimarySampleSpace {
+        PrimarySampleSpace {
+            rng: StdRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            index: 0,
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            large_step_probability: settings.large_step_probability,
+            sigma: settings.sigma
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification_iteration == self.current_iteration {
+                sample.value = sample.value_backup;
+                sample.last_modification_iteration = sample.modify_backup;
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    fn standard_normal(&mut self) -> f32 {
+        let u1 = 1.0 - self.rng.gen::<f32>();
+        let u2 = self.rng.gen::<f32>();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        //Dimensions used for the first time start from an independent value
+        while index >= self.samples.len() {
+            let value = self.rng.gen::<f32>();
+            self.samples.push(PrimarySample {
+                value,
+                last_modification_iteration: self.current_iteration,
+                value_backup: value,
+                modify_backup: self.current_iteration
+            });
+        }
+
+        //Samples untouched since the last accepted large step are reset to an independent value first
+        if self.samples[index].last_modification_iteration < self.last_large_step_iteration {
+            self.samples[index].value = self.rng.gen::<f32>();
+            self.samples[index].last_modification_iteration = self.last_large_step_iteration;
+        }
+
+        let mut sample = self.samples[index];
+        sample.value_backup = sample.value;
+        sample.modify_backup = sample.last_modification_iteration;
+        if self.large_step {
+            sample.value = self.rng.gen::<f32>();
+        } else {
+            //Apply all small steps skipped since the sample was last used at once
+            let small_steps = self.current_iteration.saturating_sub(sample.last_modification_iteration) as f32;
+            let sigma = self.sigma * small_steps.sqrt();
+            sample.value += self.standard_normal() * sigma;
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modification_iteration = self.current_iteration;
+        self.samples[index] = sample;
+    }
+
+    fn next(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+        self.ensure_ready(index);
+        self.samples[index].value
+    }
+}
+
+struct SharedSampler(Rc<RefCell<PrimarySampleSpace>>);
+
+impl Sampler for SharedSampler {
+    fn next_f32(&mut self) -> f32 {
+        self.0.borrow_mut().next()
+    }
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+//The first two primary samples choose the film position
+fn evaluate<F: Fn(f32, f32) -> Vec3>(space: &Rc<RefCell<PrimarySampleSpace>>, radiance: &F) -> (Vec3, f32, f32) {
+    sampler::scoped(Box::new(SharedSampler(space.clone())), || {
+        let s = sampler::random();
+        let t = sampler::random();
+        (radiance(s, t), s, t)
+    })
+}
+
+//Renders into the film splats and returns the splat scale normalizing them. radiance is the
+//radiance arriving at a film position.
+pub fn render<F: Fn(f32, f32) -> Vec3 + Sync>(film: &Film, mutations_per_pixel: usize, settings: &MltSettings, radiance: F) -> f32 {
+    //Bootstrap: estimate the image brightness and pick chain seeds proportionally to path luminance
+    let weights: Vec<f32> = (0..settings.bootstrap_samples).into_par_iter().map(|seed| {
+        let space = Rc::new(RefCell::new(PrimarySampleSpace::new(seed as u64, settings)));
+        let (l, _, _) = evaluate(&space, &radiance);
+        luminance(l).max(0.0)
+    }).collect();
+
+    let mut cdf = Vec::with_capacity(weights.len());
+    let mut total = 0.0;
+    for weight in weights.iter() {
+        total += weight;
+        cdf.push(total);
+    }
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let b = total / settings.bootstrap_samples as f32;
+
+    let total_mutations = mutations_per_pixel * (film.width() * film.height()) as usize;
+    let chains = settings.chains.max(1);
+
+    (0..chains).into_par_iter().for_each(|chain| {
+        let mut rng = StdRng::seed_from_u64((settings.bootstrap_samples + chain) as u64);
+        let target = rng.gen::<f32>() * total;
+        let seed = cdf.iter().position(|&c| c > target).unwrap_or(cdf.len() - 1);
+
+        //Replaying the bootstrap seed reproduces the chosen path as the initial state
+        let space = Rc::new(RefCell::new(PrimarySampleSpace::new(seed as u64, settings)));
+        let (mut l_current, mut s_current, mut t_current) = evaluate(&space, &radiance);
+
+        let mutations = total_mutations / chains + if chain < total_mutations % chains { 1 } else { 0 };
+        for _ in 0..mutations {
+            space.borrow_mut().start_iteration();
+            let (l_proposed, s_proposed, t_proposed) = evaluate(&space, &radiance);
+
+            let c_current = luminance(l_current);
+            let c_proposed = luminance(l_proposed).max(0.0);
+            let accept = if c_current > 0.0 {
+                (c_proposed / c_current).min(1.0)
+            } else {
+                1.0
+            };
+
+            //Expected values of both states are splatted regardless of the outcome
+            if accept > 0.0 && c_proposed > 0.0 {
+                film.add_splat(s_proposed, t_proposed, l_proposed * (accept / c_proposed));
+            }
+            if accept < 1.0 {
+                film.add_splat(s_current, t_current, l_current * ((1.0 - accept) / c_current));
+            }
+
+            if rng.gen::<f32>() < accept {
+                l_current = l_proposed;
+                s_current = s_proposed;
+                t_current = t_proposed;
+                space.borrow_mut().accept();
+            } else {
+                space.borrow_mut().reject();
+            }
+        }
+    });
+
+    b / mutations_per_pixel as f32
+}
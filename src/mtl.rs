@@ -0,0 +1,179 @@
+use vec3::Vec3;
+use texture::{Texture, ConstantTexture};
+use principled::PrincipledParameters;
+use std::fs;
+use std::path::Path;
+
+//Wavefront material library including the PBR extension (Pr, Pm, Ps, Pc). The obj crate's own
+//parser rejects these keys, so libraries are read here. Unknown keys are ignored.
+pub struct MtlMaterial {
+    pub name: String,
+    pub kd: Option<Vec3>,
+    pub ks: Option<Vec3>,
+    pub ke: Option<Vec3>,
+    pub ns: Option<f32>,
+    pub ni: Option<f32>,
+    pub dissolve: Option<f32>,
+    pub illum: Option<i32>,
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub sheen: Option<f32>,
+    pub clearcoat: Option<f32>,
+    //Texture file names relative to the library, the height map with its -bm multiplier
+    pub diffuse_map: Option<String>,
+    pub emission_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub bump_map: Option<(String, f32)>,
+    pub opacity_map: Option<String>
+}
+
+impl MtlMaterial {
+    fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name,
+            kd: None,
+            ks: None,
+            ke: None,
+            ns: None,
+            ni: None,
+            dissolve: None,
+            illum: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            diffuse_map: None,
+            emission_map: None,
+            normal_map: None,
+            bump_map: None,
+            opacity_map: None
+        }
+    }
+
+    pub fn emission(&self) -> Option<Vec3> {
+        self.ke.filter(|ke| ke.max_component() > 0.0)
+    }
+
+    //Dissolve of the illumination models without refraction
+    fn is_transparent(&self) -> bool {
+        matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9))
+    }
+
+    //Cutout opacity from map_d, scaled by d. Refracting illumination models turn d into
+    //transmission instead, so only their map makes a cutout.
+    pub fn opacity(&self) -> Option<(Option<&str>, f32)> {
+        let dissolve = if self.is_transparent() { 1.0 } else { self.dissolve.unwrap_or(1.0).clamp(0.0, 1.0) };
+        match self.opacity_map {
+            Some(ref file) => Some((Some(file.as_str()), dissolve)),
+            None if dissolve < 1.0 => Some((None, dissolve)),
+            None => None
+        }
+    }
+
+    //Without Pr the roughness follows from the Phong exponent. The specular level follows from the
+    //index of refraction, or without one from the mean of Ks as Blender reads it. Dissolve becomes
+    //transmission for the transparent illumination models. The loaded map_Kd texture replaces Kd,
+    //exporters tend to write a placeholder Kd next to it.
+    pub fn principled(&self, diffuse_map: Option<Box<dyn Texture + Sync>>) -> PrincipledParameters {
+        let constant = |value: f32| Box::new(ConstantTexture::new(Vec3::new(value, value, value)));
+        let base_color: Box<dyn Texture + Sync> = match diffuse_map {
+            Some(texture) => texture,
+            None => Box::new(ConstantTexture::new(self.kd.unwrap_or_else(|| Vec3::new(0.8, 0.8, 0.8))))
+        };
+        let mut parameters = PrincipledParameters::new(base_color);
+
+        let roughness = match (self.roughness, self.ns) {
+            (Some(roughness), _) => roughness,
+            (None, Some(ns)) => (2.0 / (ns.max(0.0) + 2.0)).sqrt(),
+            (None, None) => 0.5
+        };
+        parameters.roughness = constant(roughness);
+        if let Some(metallic) = self.metallic {
+            parameters.metallic = constant(metallic);
+        }
+        if let Some(sheen) = self.sheen {
+            parameters.sheen = constant(sheen);
+        }
+        if let Some(clearcoat) = self.clearcoat {
+            parameters.clearcoat = constant(clearcoat);
+        }
+        if let Some(ni) = self.ni.filter(|&ni| ni >= 1.0) {
+            let f0 = ((ni - 1.0) / (ni + 1.0)).powi(2);
+            parameters.specular = constant(f0 / 0.08);
+            parameters.ior = constant(ni);
+        } else if let Some(ks) = self.ks {
+            parameters.specular = constant((ks.x() + ks.y() + ks.z()) / 3.0);
+        }
+        if let (true, Some(dissolve)) = (self.is_transparent(), self.dissolve) {
+            parameters.transmission = constant(1.0 - dissolve);
+        }
+        parameters
+    }
+}
+
+fn parse_vec3(values: &[&str]) -> Option<Vec3> {
+    match values {
+        [x, y, z, ..] => Some(Vec3::new(x.parse().ok()?, y.parse().ok()?, z.parse().ok()?)),
+        [value] => {
+            let value = value.parse().ok()?;
+            Some(Vec3::new(value, value, value))
+        },
+        _ => None
+    }
+}
+
+//Texture statements end with the file name, options like -bm 0.5 come before it
+fn parse_map(values: &[&str]) -> Option<(String, f32)> {
+    let file = values.last()?;
+    let multiplier = values.iter().position(|value| *value == "-bm")
+        .and_then(|index| values.get(index + 1))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1.0);
+    Some((file.to_string(), multiplier))
+}
+
+fn parse_f32(values: &[&str]) -> Option<f32> {
+    values.first().and_then(|value| value.parse().ok())
+}
+
+pub fn load(path: &Path) -> Result<Vec<MtlMaterial>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for line in text.lines() {
+        let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let (key, values) = match tokens.split_first() {
+            Some((key, values)) => (*key, values),
+            None => continue
+        };
+        if key == "newmtl" {
+            materials.push(MtlMaterial::new(values.join(" ")));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue
+        };
+        match key {
+            "Kd" => material.kd = parse_vec3(values),
+            "Ks" => material.ks = parse_vec3(values),
+            "Ke" => material.ke = parse_vec3(values),
+            "Ns" => material.ns = parse_f32(values),
+            "Ni" => material.ni = parse_f32(values),
+            "d" => material.dissolve = parse_f32(values),
+            "Tr" => material.dissolve = parse_f32(values).map(|tr| 1.0 - tr),
+            "illum" => material.illum = values.first().and_then(|value| value.parse().ok()),
+            "Pr" => material.roughness = parse_f32(values),
+            "Pm" => material.metallic = parse_f32(values),
+            "Ps" => material.sheen = parse_f32(values),
+            "Pc" => material.clearcoat = parse_f32(values),
+            "map_Kd" => material.diffuse_map = parse_map(values).map(|(file, _)| file),
+            "map_Ke" => material.emission_map = parse_map(values).map(|(file, _)| file),
+            "norm" | "map_Kn" => material.normal_map = parse_map(values).map(|(file, _)| file),
+            "bump" | "map_Bump" | "map_bump" => material.bump_map = parse_map(values),
+            "map_d" => material.opacity_map = parse_map(values).map(|(file, _)| file),
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
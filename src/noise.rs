@@ -0,0 +1,470 @@
+use vec3::Vec3;
+use texture::{Texture, ColorRamp};
+
+//Seeded gradient noise and procedural textures built on it. The same seed always gives the same
+//pattern, the permutation is shuffled with a fixed generator independent of the rand crate.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    //Improved Perlin noise (Perlin 2002)
+    Perlin,
+    //Simplex noise (Perlin 2001, after Gustavson 2005)
+    Simplex
+}
+
+//Noise in about [-1, 1] at every point of space
+#[derive(Clone)]
+pub struct Noise {
+    kind: NoiseKind,
+    permutation: Vec<usize>
+}
+
+//Sum of octaves of noise, each one lacunarity times the frequency and gain times the amplitude
+//of the previous one
+#[derive(Clone, Copy)]
+pub struct Fractal {
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    Euclidean,
+    Manhattan,
+    Chebyshev
+}
+
+//Which feature point distances a Worley texture shows
+#[derive(Clone, Copy, PartialEq)]
+pub enum WorleyOutput {
+    F1,
+    F2,
+    //Zero on the borders between cells
+    F2MinusF1
+}
+
+//Cellular noise (Worley 1996) with one feature point jittered inside every unit cell
+#[derive(Clone)]
+pub struct Worley {
+    seed: u64,
+    metric: DistanceMetric
+}
+
+//Worley distances mapped through a color ramp
+pub struct WorleyTexture {
+    worley: Worley,
+    scale: f32,
+    output: WorleyOutput,
+    ramp: ColorRamp
+}
+
+//Fractal noise mapped through a color ramp, turbulence folds negative noise up
+pub struct NoiseTexture {
+    noise: Noise,
+    scale: f32,
+    fractal: Fractal,
+    ramp: ColorRamp,
+    turbulent: bool
+}
+
+//Bands along x distorted by turbulence
+pub struct MarbleTexture {
+    noise: Noise,
+    scale: f32,
+    fractal: Fractal,
+    ramp: ColorRamp,
+    distortion: f32
+}
+
+//Rings around the y axis with noisy radii, scale is the number of rings per unit
+pub struct WoodTexture {
+    noise: Noise,
+    scale: f32,
+    fractal: Fractal,
+    ramp: ColorRamp,
+    distortion: f32
+}
+
+//Speckles of high frequency turbulence
+pub struct GraniteTexture {
+    noise: Noise,
+    scale: f32,
+    fractal: Fractal,
+    ramp: ColorRamp
+}
+
+//Gradient directions towards the edges of a cube
+const GRADIENTS: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0]
+];
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+impl Noise {
+    pub fn new(kind: NoiseKind, seed: u64) -> Noise {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        let mut state = seed;
+        for i in (1..256).rev() {
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            permutation.swap(i, j);
+        }
+        let repeated = permutation.clone();
+        permutation.extend(repeated);
+        Noise {
+            kind,
+            permutation
+        }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> usize {
+        let p = &self.permutation;
+        p[p[p[(x & 255) as usize] + (y & 255) as usize] + (z & 255) as usize]
+    }
+
+    fn gradient(&self, x: i32, y: i32, z: i32, dx: f32, dy: f32, dz: f32) -> f32 {
+        let g = GRADIENTS[self.hash(x, y, z) % 12];
+        g[0] * dx + g[1] * dy + g[2] * dz
+    }
+
+    pub fn value(&self, p: Vec3) -> f32 {
+        match self.kind {
+            NoiseKind::Perlin => self.perlin(p),
+            NoiseKind::Simplex => self.simplex(p)
+        }
+    }
+
+    fn perlin(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (x, y, z) = (fx as i32, fy as i32, fz as i32);
+        let (dx, dy, dz) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+        let corner = |i: i32, j: i32, k: i32| self.gradient(x + i, y + j, z + k, dx - i as f32, dy - j as f32, dz - k as f32);
+        lerp(w,
+             lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+             lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+    }
+
+    fn simplex(&self, p: Vec3) -> f32 {
+        const SKEW: f32 = 1.0 / 3.0;
+        const UNSKEW: f32 = 1.0 / 6.0;
+        //Cell of the skewed grid and the position in it
+        let s = (p.x() + p.y() + p.z()) * SKEW;
+        let (i, j, k) = ((p.x() + s).floor(), (p.y() + s).floor(), (p.z() + s).floor());
+        let t = (i + j + k) * UNSKEW;
+        let x0 = Vec3::new(p.x() - (i - t), p.y() - (j - t), p.z() - (k - t));
+
+        //The cell splits into six tetrahedra, pick the one containing the point by ordering the
+        //coordinates
+        let (first, second) = if x0.x() >= x0.y() {
+            if x0.y() >= x0.z() {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0.x() >= x0.z() {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if x0.y() < x0.z() {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0.x() < x0.z() {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+        let mut sum = 0.0;
+        for (n, offset) in [(0, 0, 0), first, second, (1, 1, 1)].iter().enumerate() {
+            let d = x0 - Vec3::new(offset.0 as f32, offset.1 as f32, offset.2 as f32) + n as f32 * Vec3::new(UNSKEW, UNSKEW, UNSKEW);
+            let falloff = 0.6 - d.squared_length();
+            if falloff > 0.0 {
+                let falloff2 = falloff * falloff;
+                sum += falloff2 * falloff2 * self.gradient(i + offset.0, j + offset.1, k + offset.2, d.x(), d.y(), d.z());
+            }
+        }
+        32.0 * sum
+    }
+
+    //Fractal Brownian motion normalized to about [-1, 1]
+    pub fn fbm(&self, p: Vec3, fractal: &Fractal) -> f32 {
+        fractal.sum(|frequency| self.value(p * frequency))
+    }
+
+    //Sum of absolute octaves normalized to about [0, 1]
+    pub fn turbulence(&self, p: Vec3, fractal: &Fractal) -> f32 {
+        fractal.sum(|frequency| self.value(p * frequency).abs())
+    }
+}
+
+impl Fractal {
+    pub fn new(octaves: u32) -> Fractal {
+        Fractal {
+            octaves: octaves.max(1),
+            lacunarity: 2.0,
+            gain: 0.5
+        }
+    }
+
+    pub fn with_lacunarity(self, lacunarity: f32) -> Fractal {
+        Fractal {
+            lacunarity,
+            ..self
+        }
+    }
+
+    pub fn with_gain(self, gain: f32) -> Fractal {
+        Fractal {
+            gain,
+            ..self
+        }
+    }
+
+    //Weighted sum of octave(frequency) divided by the sum of the weights
+    fn sum<F: Fn(f32) -> f32>(&self, octave: F) -> f32 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for _ in 0..self.octaves {
+            sum += amplitude * octave(frequency);
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum / total
+    }
+}
+
+impl Worley {
+    pub fn new(seed: u64, metric: DistanceMetric) -> Worley {
+        Worley {
+            seed,
+            metric
+        }
+    }
+
+    fn feature_point(&self, x: i32, y: i32, z: i32) -> Vec3 {
+        let mut state = self.seed ^ (x as u32 as u64).wrapping_mul(0x8da6_b343)
+            ^ (y as u32 as u64).wrapping_mul(0xd816_3841) ^ (z as u32 as u64).wrapping_mul(0xcb1a_b31f).rotate_left(32);
+        let mut jitter = || (splitmix64(&mut state) >> 40) as f32 / (1u64 << 24) as f32;
+        Vec3::new(x as f32 + jitter(), y as f32 + jitter(), z as f32 + jitter())
+    }
+
+    fn distance(&self, d: Vec3) -> f32 {
+        let (x, y, z) = (d.x().abs(), d.y().abs(), d.z().abs());
+        match self.metric {
+            DistanceMetric::Euclidean => d.length(),
+            DistanceMetric::Manhattan => x + y + z,
+            DistanceMetric::Chebyshev => x.max(y).max(z)
+        }
+    }
+
+    //Distances to the closest and second closest feature points
+    pub fn distances(&self, p: Vec3) -> (f32, f32) {
+        let (x, y, z) = (p.x().floor() as i32, p.y().floor() as i32, p.z().floor() as i32);
+        let mut f1 = f32::MAX;
+        let mut f2 = f32::MAX;
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let d = self.distance(self.feature_point(x + i, y + j, z + k) - p);
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl WorleyTexture {
+    pub fn new(worley: Worley, scale: f32, output: WorleyOutput, ramp: ColorRamp) -> WorleyTexture {
+        WorleyTexture {
+            worley,
+            scale,
+            output,
+            ramp
+        }
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let (f1, f2) = self.worley.distances(*p * self.scale);
+        self.ramp.value(match self.output {
+            WorleyOutput::F1 => f1,
+            WorleyOutput::F2 => f2,
+            WorleyOutput::F2MinusF1 => f2 - f1
+        })
+    }
+}
+
+impl NoiseTexture {
+    pub fn new(noise: Noise, scale: f32, fractal: Fractal, ramp: ColorRamp) -> NoiseTexture {
+        NoiseTexture {
+            noise,
+            scale,
+            fractal,
+            ramp,
+            turbulent: false
+        }
+    }
+
+    pub fn turbulent(self) -> NoiseTexture {
+        NoiseTexture {
+            turbulent: true,
+            ..self
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let p = *p * self.scale;
+        let t = if self.turbulent {
+            self.noise.turbulence(p, &self.fractal)
+        } else {
+            0.5 * (self.noise.fbm(p, &self.fractal) + 1.0)
+        };
+        self.ramp.value(t)
+    }
+}
+
+impl MarbleTexture {
+    pub fn new(noise: Noise, scale: f32, fractal: Fractal, ramp: ColorRamp) -> MarbleTexture {
+        MarbleTexture {
+            noise,
+            scale,
+            fractal,
+            ramp,
+            distortion: 3.0
+        }
+    }
+
+    pub fn with_distortion(self, distortion: f32) -> MarbleTexture {
+        MarbleTexture {
+            distortion,
+            ..self
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let p = *p * self.scale;
+        let phase = p.x() + self.distortion * self.noise.turbulence(p, &self.fractal);
+        self.ramp.value(0.5 * (phase.sin() + 1.0))
+    }
+}
+
+impl WoodTexture {
+    pub fn new(noise: Noise, scale: f32, fractal: Fractal, ramp: ColorRamp) -> WoodTexture {
+        WoodTexture {
+            noise,
+            scale,
+            fractal,
+            ramp,
+            distortion: 0.6
+        }
+    }
+
+    pub fn with_distortion(self, distortion: f32) -> WoodTexture {
+        WoodTexture {
+            distortion,
+            ..self
+        }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt() * self.scale;
+        //Stretch the noise along the grain
+        let grain = Vec3::new(p.x() * self.scale, p.y() * self.scale * 0.1, p.z() * self.scale);
+        let rings = radius + self.distortion * self.noise.fbm(grain, &self.fractal);
+        self.ramp.value(rings - rings.floor())
+    }
+}
+
+impl GraniteTexture {
+    pub fn new(noise: Noise, scale: f32, fractal: Fractal, ramp: ColorRamp) -> GraniteTexture {
+        GraniteTexture {
+            noise,
+            scale,
+            fractal,
+            ramp
+        }
+    }
+}
+
+impl Texture for GraniteTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let t = self.noise.turbulence(*p * self.scale, &self.fractal);
+        //Sharpen the contrast into grains
+        self.ramp.value(((t - 0.1) * 2.5).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Fixed points scattered over many lattice cells
+    fn points() -> Vec<Vec3> {
+        (0..4096).map(|i| {
+            let i = i as f32;
+            Vec3::new(i * 0.137 - 40.0, (i * 0.291).sin() * 30.0, (i * 0.053).cos() * 50.0 + i * 0.011)
+        }).collect()
+    }
+
+    #[test]
+    fn noise_is_deterministic_given_a_seed() {
+        for &kind in [NoiseKind::Perlin, NoiseKind::Simplex].iter() {
+            let first = Noise::new(kind, 7);
+            let second = Noise::new(kind, 7);
+            let other = Noise::new(kind, 8);
+            let values = |noise: &Noise| points().iter().map(|p| noise.value(*p)).collect::<Vec<f32>>();
+            assert_eq!(values(&first), values(&second));
+            assert_ne!(values(&first), values(&other));
+        }
+    }
+
+    #[test]
+    fn worley_is_deterministic_given_a_seed() {
+        let first = Worley::new(7, DistanceMetric::Euclidean);
+        let second = Worley::new(7, DistanceMetric::Euclidean);
+        let other = Worley::new(8, DistanceMetric::Euclidean);
+        let distances = |worley: &Worley| points().iter().map(|p| worley.distances(*p)).collect::<Vec<(f32, f32)>>();
+        assert_eq!(distances(&first), distances(&second));
+        assert_ne!(distances(&first), distances(&other));
+    }
+
+    #[test]
+    fn noise_stays_within_unit_range() {
+        for &kind in [NoiseKind::Perlin, NoiseKind::Simplex].iter() {
+            let noise = Noise::new(kind, 7);
+            let largest = points().iter().map(|p| noise.value(*p).abs()).fold(0.0, f32::max);
+            assert!(largest > 0.5 && largest <= 1.05, "largest value {}", largest);
+        }
+    }
+}
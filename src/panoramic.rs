@@ -0,0 +1,392 @@
+use vec3::Vec3;
+use ray::{Ray, RayDifferential};
+use camera::{Camera, CameraSample, CameraFrame, pixel_spacing};
+use std::f32::consts::PI;
+
+//Cameras seeing more than a perspective projection can hold, all rays leave a single point
+//except in stereo. Like a pinhole they count as a lens of unit area for light tracing, with the
+//density of directions over the film as importance.
+
+//Ray through film position (s, t) from a mapping to origins and local directions, with
+//differential rays through the neighbouring pixels while those are still on the image
+fn panoramic_ray<F: Fn(f32, f32) -> Option<(Vec3, Vec3)>>(frame: &CameraFrame, s: f32, t: f32, pixel_spacing: Option<(f32, f32)>, map: F) -> Option<Ray> {
+    let (origin, direction) = map(s, t)?;
+    let ray = Ray::new(origin, frame.to_world(direction));
+    let (ds, dt) = match pixel_spacing {
+        Some(spacing) => spacing,
+        None => return Some(ray)
+    };
+    match (map(s + ds, t), map(s, t + dt)) {
+        (Some((rx_origin, rx_direction)), Some((ry_origin, ry_direction))) => Some(ray.with_differential(RayDifferential {
+            rx_origin,
+            rx_direction: frame.to_world(rx_direction),
+            ry_origin,
+            ry_direction: frame.to_world(ry_direction)
+        })),
+        _ => Some(ray)
+    }
+}
+
+//Light tracing connection to the center of projection. raster maps a local direction to its
+//film position and directional density.
+fn connect_center<F: Fn(Vec3) -> Option<(f32, f32, f32)>>(frame: &CameraFrame, point: Vec3, raster: F) -> Option<CameraSample> {
+    let to_camera = frame.origin - point;
+    let dist = to_camera.length();
+    if dist == 0.0 {
+        return None;
+    }
+    let wi = to_camera / dist;
+    let (s, t, pdf_dir) = raster(frame.to_local(-wi))?;
+    Some(CameraSample {
+        wi,
+        dist,
+        pdf: dist * dist,
+        importance: pdf_dir,
+        s,
+        t,
+        lens_point: frame.origin
+    })
+}
+
+//Latitude-longitude panorama covering all directions, the forward direction at its center. In
+//stereo the two eyes are stacked, the left one on top.
+#[derive(Debug)]
+pub struct EquirectangularCamera {
+    frame: CameraFrame,
+    //Interpupillary distance of omni-directional stereo, zero for a single eye
+    ipd: f32,
+    pixel_spacing: Option<(f32, f32)>
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> EquirectangularCamera {
+        EquirectangularCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup),
+            ipd: 0.0,
+            pixel_spacing: None
+        }
+    }
+
+    //Omni-directional stereo, each eye sits on a circle of diameter ipd and looks along its tangent
+    pub fn with_stereo(self, ipd: f32) -> EquirectangularCamera {
+        EquirectangularCamera {
+            ipd,
+            ..self
+        }
+    }
+
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> EquirectangularCamera {
+        EquirectangularCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    fn stereo(&self) -> bool {
+        self.ipd > 0.0
+    }
+
+    fn map(&self, s: f32, t: f32) -> Option<(Vec3, Vec3)> {
+        if !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+        let (t, eye) = if !self.stereo() {
+            (t, 0.0)
+        } else if t >= 0.5 {
+            (2.0 * t - 1.0, -1.0)
+        } else {
+            (2.0 * t, 1.0)
+        };
+        let phi = (s - 0.5) * 2.0 * PI;
+        let theta = (t - 0.5) * PI;
+        let direction = Vec3::new(theta.cos() * phi.sin(), theta.sin(), theta.cos() * phi.cos());
+        let offset = eye * 0.5 * self.ipd * Vec3::new(phi.cos(), 0.0, -phi.sin());
+        Some((self.frame.origin + self.frame.to_world(offset), direction))
+    }
+
+    //Each eye covers the sphere with a share of the film, the density grows towards the poles
+    fn pdf_direction(&self, direction: Vec3) -> f32 {
+        let cos_theta = (1.0 - direction.y() * direction.y()).max(0.0).sqrt();
+        let share = if self.stereo() { 0.5 } else { 1.0 };
+        if cos_theta == 0.0 {
+            0.0
+        } else {
+            1.0 / (share * 2.0 * PI * PI * cos_theta)
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn forward(&self) -> Vec3 {
+        self.frame.forward
+    }
+
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32) {
+        (1.0, self.pdf_direction(Vec3::unit_vector(self.frame.to_local(r.direction()))))
+    }
+
+    //The eyes of the stereo camera move with the direction, only the single eye one is reached
+    fn sample_importance(&self, point: Vec3) -> Option<CameraSample> {
+        if self.stereo() {
+            return None;
+        }
+        connect_center(&self.frame, point, |d| {
+            let s = 0.5 + d.x().atan2(d.z()) / (2.0 * PI);
+            let t = 0.5 + d.y().clamp(-1.0, 1.0).asin() / PI;
+            Some((s, t, self.pdf_direction(d)))
+        })
+    }
+
+    fn connectable(&self) -> bool {
+        !self.stereo()
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        panoramic_ray(&self.frame, s, t, self.pixel_spacing, |s, t| self.map(s, t))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    //Distance from the image center proportional to the angle off the axis
+    Equidistant,
+    //Image area proportional to solid angle
+    Equisolid
+}
+
+//Circular fisheye, the image circle touches the top and bottom of the film
+#[derive(Debug)]
+pub struct FisheyeCamera {
+    frame: CameraFrame,
+    //Angle off the axis at the edge of the image circle, up to pi for a full sphere
+    half_fov: f32,
+    mapping: FisheyeMapping,
+    aspect: f32,
+    pixel_spacing: Option<(f32, f32)>
+}
+
+impl FisheyeCamera {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, fov: f32, mapping: FisheyeMapping, aspect: f32) -> FisheyeCamera {
+        FisheyeCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup),
+            half_fov: fov.clamp(1.0, 360.0).to_radians() / 2.0,
+            mapping,
+            aspect,
+            pixel_spacing: None
+        }
+    }
+
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> FisheyeCamera {
+        FisheyeCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    fn map(&self, s: f32, t: f32) -> Option<(Vec3, Vec3)> {
+        let x = (2.0 * s - 1.0) * self.aspect;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).asin()
+        };
+        let direction = if r > 0.0 {
+            Vec3::new(theta.sin() * x / r, theta.sin() * y / r, theta.cos())
+        } else {
+            Vec3::new(0.0, 0.0, 1.0)
+        };
+        Some((self.frame.origin, direction))
+    }
+
+    fn raster(&self, direction: Vec3) -> Option<(f32, f32, f32)> {
+        let theta = direction.z().clamp(-1.0, 1.0).acos();
+        if theta > self.half_fov {
+            return None;
+        }
+        let r = match self.mapping {
+            FisheyeMapping::Equidistant => theta / self.half_fov,
+            FisheyeMapping::Equisolid => (theta / 2.0).sin() / (self.half_fov / 2.0).sin()
+        };
+        let sin_theta = theta.sin();
+        let (x, y) = if sin_theta > 0.0 {
+            (r * direction.x() / sin_theta, r * direction.y() / sin_theta)
+        } else {
+            (0.0, 0.0)
+        };
+        let s = 0.5 + 0.5 * x / self.aspect;
+        let t = 0.5 + 0.5 * y;
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+        Some((s, t, self.pdf_direction(theta)))
+    }
+
+    //The film spans 2 aspect by 2 units of the image plane the circle of radius one lies in
+    fn pdf_direction(&self, theta: f32) -> f32 {
+        match self.mapping {
+            FisheyeMapping::Equidistant => {
+                let sinc = if theta > 1e-4 { theta.sin() / theta } else { 1.0 };
+                1.0 / (4.0 * self.aspect * self.half_fov * self.half_fov * sinc)
+            },
+            FisheyeMapping::Equisolid => {
+                let k = (self.half_fov / 2.0).sin();
+                1.0 / (16.0 * self.aspect * k * k)
+            }
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn forward(&self) -> Vec3 {
+        self.frame.forward
+    }
+
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32) {
+        match self.raster(Vec3::unit_vector(self.frame.to_local(r.direction()))) {
+            Some((_, _, pdf)) => (1.0, pdf),
+            None => (0.0, 0.0)
+        }
+    }
+
+    fn sample_importance(&self, point: Vec3) -> Option<CameraSample> {
+        connect_center(&self.frame, point, |d| self.raster(d))
+    }
+
+    fn connectable(&self) -> bool {
+        true
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        panoramic_ray(&self.frame, s, t, self.pixel_spacing, |s, t| self.map(s, t))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CubemapLayout {
+    //The faces side by side in the order of CUBE_FACES, to be cut into one image each
+    Faces,
+    //Horizontal cross of 4 by 3 faces, up and down above and below the front
+    Cross
+}
+
+pub const CUBE_FACES: [&str; 6] = ["right", "left", "up", "down", "front", "back"];
+
+//Face normals and the directions of the image right and up on them, in the order of CUBE_FACES
+const FACE_BASES: [[(f32, f32, f32); 3]; 6] = [
+    [(1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0)],
+    [(-1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 0.0)],
+    [(0.0, 1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, -1.0)],
+    [(0.0, -1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)],
+    [(0.0, 0.0, 1.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+    [(0.0, 0.0, -1.0), (-1.0, 0.0, 0.0), (0.0, 1.0, 0.0)]
+];
+
+//Column and row from the bottom of each face in the cross, in the order of CUBE_FACES
+const CROSS_CELLS: [(usize, usize); 6] = [(2, 1), (0, 1), (1, 2), (1, 0), (1, 1), (3, 1)];
+
+fn face_basis(face: usize) -> [Vec3; 3] {
+    let basis = FACE_BASES[face];
+    [Vec3::new(basis[0].0, basis[0].1, basis[0].2), Vec3::new(basis[1].0, basis[1].1, basis[1].2), Vec3::new(basis[2].0, basis[2].1, basis[2].2)]
+}
+
+//Six 90 degree perspective views along the axes of the camera frame
+#[derive(Debug)]
+pub struct CubemapCamera {
+    frame: CameraFrame,
+    layout: CubemapLayout,
+    pixel_spacing: Option<(f32, f32)>
+}
+
+impl CubemapCamera {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, layout: CubemapLayout) -> CubemapCamera {
+        CubemapCamera {
+            frame: CameraFrame::new(lookfrom, lookat, vup),
+            layout,
+            pixel_spacing: None
+        }
+    }
+
+    pub fn with_differentials(self, width: u32, height: u32, samples_per_pixel: usize) -> CubemapCamera {
+        CubemapCamera {
+            pixel_spacing: Some(pixel_spacing(width, height, samples_per_pixel)),
+            ..self
+        }
+    }
+
+    //Faces across and up the film
+    fn grid(&self) -> (usize, usize) {
+        match self.layout {
+            CubemapLayout::Faces => (6, 1),
+            CubemapLayout::Cross => (4, 3)
+        }
+    }
+
+    fn cell(&self, face: usize) -> (usize, usize) {
+        match self.layout {
+            CubemapLayout::Faces => (face, 0),
+            CubemapLayout::Cross => CROSS_CELLS[face]
+        }
+    }
+
+    fn map(&self, s: f32, t: f32) -> Option<(Vec3, Vec3)> {
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return None;
+        }
+        let (columns, rows) = self.grid();
+        let x = s * columns as f32;
+        let y = t * rows as f32;
+        let cell = (x as usize, y as usize);
+        let face = (0..6).find(|&face| self.cell(face) == cell)?;
+        let a = 2.0 * x.fract() - 1.0;
+        let b = 2.0 * y.fract() - 1.0;
+        let [normal, right, up] = face_basis(face);
+        Some((self.frame.origin, normal + a * right + b * up))
+    }
+
+    fn raster(&self, direction: Vec3) -> Option<(f32, f32, f32)> {
+        let face = (0..6).max_by(|&i, &j| face_basis(i)[0].dot(direction).partial_cmp(&face_basis(j)[0].dot(direction)).unwrap())?;
+        let [normal, right, up] = face_basis(face);
+        let along = direction.dot(normal);
+        if along <= 0.0 {
+            return None;
+        }
+        let a = direction.dot(right) / along;
+        let b = direction.dot(up) / along;
+        let (columns, rows) = self.grid();
+        let (column, row) = self.cell(face);
+        let s = (column as f32 + 0.5 * (a + 1.0)) / columns as f32;
+        let t = (row as f32 + 0.5 * (b + 1.0)) / rows as f32;
+        //A face spans 2 by 2 units of its image plane at unit distance
+        let pdf = (1.0 + a * a + b * b).powf(1.5) / (4.0 * (columns * rows) as f32);
+        Some((s, t, pdf))
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn forward(&self) -> Vec3 {
+        self.frame.forward
+    }
+
+    fn pdf_importance(&self, r: &Ray) -> (f32, f32) {
+        match self.raster(Vec3::unit_vector(self.frame.to_local(r.direction()))) {
+            Some((_, _, pdf)) => (1.0, pdf),
+            None => (0.0, 0.0)
+        }
+    }
+
+    fn sample_importance(&self, point: Vec3) -> Option<CameraSample> {
+        connect_center(&self.frame, point, |d| self.raster(d))
+    }
+
+    fn connectable(&self) -> bool {
+        true
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        panoramic_ray(&self.frame, s, t, self.pixel_spacing, |s, t| self.map(s, t))
+    }
+}
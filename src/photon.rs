@@ -0,0 +1,310 @@
+use vec3::Vec3;
+use ray::Ray;
+use camera::Camera;
+use film::Film;
+use hitable::Hitable;
+use light::LightList;
+use material::Material;
+use texture::TexCoord;
+use sampler;
+
+extern crate rayon;
+use rayon::prelude::*;
+
+pub struct PhotonSettings {
+    //Photons traced per photon map
+    pub photon_count: usize,
+    //Gather radius, the initial radius for progressive rendering
+    pub radius: f32,
+    //Fraction of newly found photons kept per progressive iteration
+    pub alpha: f32,
+    pub max_depth: u32
+}
+
+#[derive(Clone, Copy)]
+struct Photon {
+    p: Vec3,
+    //Direction the photon arrived from, pointing away from the surface
+    wi: Vec3,
+    power: Vec3
+}
+
+fn component(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z()
+    }
+}
+
+//Left balanced kd-tree stored in place: the median of every range is the node splitting it
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        PhotonMap::build(&mut photons, &mut axes);
+        PhotonMap {
+            photons,
+            axes
+        }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [usize]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let mut min = photons[0].p;
+        let mut max = photons[0].p;
+        for photon in photons.iter() {
+            min = Vec3::new(min.x().min(photon.p.x()), min.y().min(photon.p.y()), min.z().min(photon.p.z()));
+            max = Vec3::new(max.x().max(photon.p.x()), max.y().max(photon.p.y()), max.z().max(photon.p.z()));
+        }
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| {
+            component(a.p, axis).partial_cmp(&component(b.p, axis)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        axes[mid] = axis;
+
+        let (left_photons, right_photons) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        PhotonMap::build(left_photons, left_axes);
+        PhotonMap::build(&mut right_photons[1..], &mut right_axes[1..]);
+    }
+
+    fn query<F: FnMut(&Photon)>(&self, p: Vec3, radius2: f32, f: &mut F) {
+        self.query_range(0, self.photons.len(), p, radius2, f);
+    }
+
+    fn query_range<F: FnMut(&Photon)>(&self, start: usize, end: usize, p: Vec3, radius2: f32, f: &mut F) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - p).squared_length() <= radius2 {
+            f(photon);
+        }
+        if end - start == 1 {
+            return;
+        }
+
+        let axis = self.axes[mid];
+        let delta = component(p, axis) - component(photon.p, axis);
+        if delta <= 0.0 {
+            self.query_range(start, mid, p, radius2, f);
+            if delta * delta <= radius2 {
+                self.query_range(mid + 1, end, p, radius2, f);
+            }
+        } else {
+            self.query_range(mid + 1, end, p, radius2, f);
+            if delta * delta <= radius2 {
+                self.query_range(start, mid, p, radius2, f);
+            }
+        }
+    }
+}
+
+//Photons are stored at every non specular surface interaction, participating media are only passed through
+fn trace_photon(world: &dyn Hitable, lights: &LightList, photon_count: usize, max_depth: u32, photons: &mut Vec<Photon>) {
+    let emission = match lights.sample_emission() {
+        Some(emission) => emission,
+        None => return
+    };
+    if emission.pdf_dir <= 0.0 {
+        return;
+    }
+    let cos = emission.normal.dot(emission.ray.direction()).abs();
+    let mut power = emission.le * (cos / (emission.pdf_pos * emission.pdf_dir * photon_count as f32));
+    let mut ray = emission.ray;
+
+    for depth in 0..max_depth {
+        let hit = match world.hit(0.001, f32::MAX, &ray) {
+            Some(hit) => hit,
+            None => break
+        };
+        let scatter_rec = match hit.scatter(&ray) {
+            Some(scatter_rec) => scatter_rec,
+            None => break
+        };
+        let on_surface = hit.normal.squared_length() > 0.0;
+        if !scatter_rec.is_specular && on_surface {
+            photons.push(Photon {
+                p: hit.p,
+                wi: Vec3::unit_vector(-ray.direction()),
+                power
+            });
+        }
+
+        let new_power = power * scatter_rec.attenuation;
+        //Russian roulette keeps photon powers roughly constant
+        if depth > 0 {
+            let survive_prob = (new_power.max_component() / power.max_component()).min(1.0);
+            if sampler::random() >= survive_prob {
+                break;
+            }
+            power = new_power / survive_prob;
+        } else {
+            power = new_power;
+        }
+        ray = scatter_rec.scattered;
+    }
+}
+
+fn trace_photons(world: &(dyn Hitable + Sync), lights: &LightList, photon_count: usize, max_depth: u32) -> PhotonMap {
+    let photons = (0..photon_count).into_par_iter().fold(Vec::new, |mut photons, _| {
+        trace_photon(world, lights, photon_count, max_depth, &mut photons);
+        photons
+    }).reduce(Vec::new, |mut a, mut b| {
+        a.append(&mut b);
+        a
+    });
+    PhotonMap::new(photons)
+}
+
+//First non specular surface seen from the camera, with the emission collected on the way there
+struct VisiblePoint<'a> {
+    p: Vec3,
+    normal: Vec3,
+    uv: TexCoord,
+    wo: Vec3,
+    beta: Vec3,
+    material: &'a (dyn Material + Sync)
+}
+
+fn visible_point<'a>(world: &'a (dyn Hitable + Sync), r: &Ray, max_depth: u32, emitted: &mut Vec3) -> Option<VisiblePoint<'a>> {
+    let mut beta = Vec3::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+
+    for _ in 0..max_depth {
+        let hit = world.hit(0.001, f32::MAX, &ray)?;
+        *emitted = *emitted + beta * hit.material.emitted(hit.uv.u, hit.uv.v, &hit.p);
+        let scatter_rec = hit.scatter(&ray)?;
+        let on_surface = hit.normal.squared_length() > 0.0;
+        if !scatter_rec.is_specular && on_surface {
+            return Some(VisiblePoint {
+                p: hit.p,
+                normal: hit.normal,
+                uv: hit.uv,
+                wo: Vec3::unit_vector(-ray.direction()),
+                beta,
+                material: hit.material
+            });
+        }
+        beta = beta * scatter_rec.attenuation;
+        ray = scatter_rec.scattered;
+    }
+    None
+}
+
+//Sum of BSDF weighted photon power within the radius, and the number of photons found
+fn gather(map: &PhotonMap, vp: &VisiblePoint, radius2: f32) -> (Vec3, usize) {
+    let mut flux = Vec3::zero_vector();
+    let mut count = 0;
+    map.query(vp.p, radius2, &mut |photon| {
+        flux = flux + photon.power * vp.material.eval(vp.wo, photon.wi, vp.p, vp.normal, vp.uv);
+        count += 1;
+    });
+    (flux, count)
+}
+
+fn random_film_position(x: u32, y: u32, width: u32, height: u32) -> (f32, f32) {
+    let u = (x as f32 + sampler::random()) / width as f32;
+    let v = (y as f32 + sampler::random()) / height as f32;
+    (u, v)
+}
+
+//Classic photon mapping: one photon map shared by every camera sample with a fixed gather radius
+pub fn render(camera: &(dyn Camera + Sync), world: &(dyn Hitable + Sync), lights: &LightList, film: &mut Film, samples_per_pixel: usize, settings: &PhotonSettings) {
+    let max_depth = settings.max_depth;
+    let map = trace_photons(world, lights, settings.photon_count, max_depth);
+    let radius2 = settings.radius * settings.radius;
+    let width = film.width();
+    let height = film.height();
+
+    for y in (0..height).rev() {
+        let row: Vec<Vec3> = (0..width).into_par_iter().map(|x| {
+            let mut sum = Vec3::zero_vector();
+            for _ in 0..samples_per_pixel {
+                let (u, v) = random_film_position(x, y, width, height);
+                let mut emitted = Vec3::zero_vector();
+                if let Some(vp) = camera.get_ray(u, v).and_then(|r| visible_point(world, &r, max_depth, &mut emitted)) {
+                    let (flux, _) = gather(&map, &vp, radius2);
+                    sum = sum + vp.beta * flux / (std::f32::consts::PI * radius2);
+                }
+                sum = sum + emitted;
+            }
+            sum / samples_per_pixel as f32
+        }).collect();
+
+        for (x, color) in row.into_iter().enumerate() {
+            film.set_pixel(x as u32, y, color);
+        }
+        print!("{} / {} scanlines rendered \r", (height - y), height)
+    }
+}
+
+struct PixelState {
+    radius2: f32,
+    photons: f32,
+    tau: Vec3,
+    emitted: Vec3
+}
+
+//Stochastic progressive photon mapping (Hachisuka and Jensen 2009): every iteration traces a new
+//visible point per pixel and a new photon map, gather radii shrink with the number of photons found
+pub fn render_progressive(camera: &(dyn Camera + Sync), world: &(dyn Hitable + Sync), lights: &LightList, film: &mut Film, iterations: usize, settings: &PhotonSettings) {
+    let max_depth = settings.max_depth;
+    let alpha = settings.alpha;
+    let width = film.width();
+    let height = film.height();
+    let mut pixels: Vec<PixelState> = (0..width * height).map(|_| PixelState {
+        radius2: settings.radius * settings.radius,
+        photons: 0.0,
+        tau: Vec3::zero_vector(),
+        emitted: Vec3::zero_vector()
+    }).collect();
+
+    for iteration in 0..iterations {
+        let map = trace_photons(world, lights, settings.photon_count, max_depth);
+
+        pixels.par_iter_mut().enumerate().for_each(|(index, pixel)| {
+            let x = index as u32 % width;
+            let y = index as u32 / width;
+            let (u, v) = random_film_position(x, y, width, height);
+            let mut emitted = Vec3::zero_vector();
+            if let Some(vp) = camera.get_ray(u, v).and_then(|r| visible_point(world, &r, max_depth, &mut emitted)) {
+                let (flux, count) = gather(&map, &vp, pixel.radius2);
+                if count > 0 {
+                    let photons = pixel.photons + alpha * count as f32;
+                    let radius2 = pixel.radius2 * photons / (pixel.photons + count as f32);
+                    pixel.tau = (pixel.tau + vp.beta * flux) * (radius2 / pixel.radius2);
+                    pixel.photons = photons;
+                    pixel.radius2 = radius2;
+                }
+            }
+            pixel.emitted = pixel.emitted + emitted;
+        });
+
+        print!("{} / {} iterations rendered \r", iteration + 1, iterations)
+    }
+
+    for (index, pixel) in pixels.iter().enumerate() {
+        let x = index as u32 % width;
+        let y = index as u32 / width;
+        let color = (pixel.emitted + pixel.tau / (std::f32::consts::PI * pixel.radius2)) / iterations as f32;
+        film.set_pixel(x, y, color);
+    }
+}
@@ -0,0 +1,253 @@
+use vec3::Vec3;
+use ray::Ray;
+use texture::{Texture, TexCoord, ConstantTexture};
+use material::{Material, ScatterRecord, random_cosine_direction, outward_normal};
+use microfacet::{Frame, TrowbridgeReitz};
+use microfacet;
+use sampler;
+
+//Parameters of the principled BSDF, scalar parameters are read from the first channel of their
+//texture. Ranges follow the Disney BRDF, all in [0, 1] except ior.
+pub struct PrincipledParameters {
+    pub base_color: Box<dyn Texture + Sync>,
+    pub metallic: Box<dyn Texture + Sync>,
+    pub roughness: Box<dyn Texture + Sync>,
+    pub specular: Box<dyn Texture + Sync>,
+    pub specular_tint: Box<dyn Texture + Sync>,
+    pub sheen: Box<dyn Texture + Sync>,
+    pub clearcoat: Box<dyn Texture + Sync>,
+    pub transmission: Box<dyn Texture + Sync>,
+    pub ior: Box<dyn Texture + Sync>
+}
+
+//Uber-material after the Disney principled BSDF (Burley 2012, 2015). It blends a diffuse base
+//with sheen, a GGX specular lobe shared by dielectric and metallic surfaces, a rough glass lobe
+//for transmission and a clearcoat layer.
+pub struct Principled {
+    parameters: PrincipledParameters
+}
+
+//Parameters evaluated at a point
+struct Shading {
+    base_color: Vec3,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    clearcoat: f32,
+    transmission: f32,
+    ior: f32,
+    distribution: TrowbridgeReitz
+}
+
+//Smoother than this the lobes would approach delta distributions, which cannot be mixed
+const MIN_ROUGHNESS: f32 = 0.05;
+
+const CLEARCOAT_ALPHA: f32 = 0.03;
+
+fn constant(value: f32) -> Box<dyn Texture + Sync> {
+    Box::new(ConstantTexture::new(Vec3::new(value, value, value)))
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + schlick_weight(cos_theta) * (Vec3::new(1.0, 1.0, 1.0) - f0)
+}
+
+impl PrincipledParameters {
+    //Defaults of the Disney BRDF: a rough dielectric with specular 0.5, which is an index of
+    //refraction of 1.5, and no sheen, clearcoat or transmission
+    pub fn new(base_color: Box<dyn Texture + Sync>) -> PrincipledParameters {
+        PrincipledParameters {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            transmission: constant(0.0),
+            ior: constant(1.5)
+        }
+    }
+}
+
+impl Principled {
+    pub fn new(parameters: PrincipledParameters) -> Principled {
+        Principled {
+            parameters
+        }
+    }
+
+    fn shading(&self, point: Vec3, uv: TexCoord) -> Shading {
+        let scalar = |texture: &dyn Texture| texture.filtered(&uv, &point).x();
+        let parameters = &self.parameters;
+        let roughness = scalar(&*parameters.roughness).clamp(MIN_ROUGHNESS, 1.0);
+        Shading {
+            base_color: parameters.base_color.filtered(&uv, &point),
+            metallic: scalar(&*parameters.metallic).clamp(0.0, 1.0),
+            roughness,
+            specular: scalar(&*parameters.specular).max(0.0),
+            specular_tint: scalar(&*parameters.specular_tint).clamp(0.0, 1.0),
+            sheen: scalar(&*parameters.sheen).max(0.0),
+            clearcoat: scalar(&*parameters.clearcoat).max(0.0),
+            transmission: scalar(&*parameters.transmission).clamp(0.0, 1.0),
+            ior: scalar(&*parameters.ior).max(1.0001),
+            distribution: TrowbridgeReitz::from_roughness(roughness, 0.0)
+        }
+    }
+
+    //Opaque surfaces are two sided, transmissive ones keep the normal to tell inside from outside
+    fn frame(shading: &Shading, wo: Vec3, normal: Vec3) -> Frame {
+        if wo.dot(normal) < 0.0 && shading.transmission <= 0.0 {
+            Frame::new(-normal)
+        } else {
+            Frame::new(normal)
+        }
+    }
+}
+
+impl Shading {
+    fn tint(&self) -> Vec3 {
+        let l = luminance(self.base_color);
+        if l > 0.0 {
+            self.base_color / l
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    //Probabilities of sampling the diffuse, specular, glass and clearcoat lobes. Inside a
+    //transmissive surface only the glass lobe exists.
+    fn lobe_probabilities(&self, wo: Vec3) -> [f32; 4] {
+        if wo.z() < 0.0 {
+            return [0.0, 0.0, 1.0, 0.0];
+        }
+        let opaque = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let weights = [
+            opaque,
+            self.metallic + 0.25 * opaque,
+            (1.0 - self.metallic) * self.transmission,
+            0.25 * self.clearcoat
+        ];
+        let total: f32 = weights.iter().sum();
+        [weights[0] / total, weights[1] / total, weights[2] / total, weights[3] / total]
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        //Glass tints what it transmits with the base color
+        let glass = |weight: f32| {
+            let (reflected, transmitted) = self.distribution.eval_dielectric(wo, wi, self.ior);
+            weight * (Vec3::new(reflected, reflected, reflected) + transmitted * self.base_color)
+        };
+        if wo.z() < 0.0 {
+            return glass(1.0);
+        }
+        let mut f = glass((1.0 - self.metallic) * self.transmission);
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return f;
+        }
+
+        let wh = Vec3::unit_vector(wo + wi);
+        let cos_o = wo.z();
+        let cos_i = wi.z();
+        let cos_d = wi.dot(wh);
+        let opaque = (1.0 - self.metallic) * (1.0 - self.transmission);
+
+        //Burley diffuse with retro-reflection and sheen
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i)) * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+        let sheen_color = lerp(Vec3::new(1.0, 1.0, 1.0), self.tint(), 0.5);
+        f = f + opaque * (diffuse / std::f32::consts::PI * self.base_color + self.sheen * schlick_weight(cos_d) * sheen_color);
+
+        //Specular reflection blending dielectric and metallic Fresnel
+        let f0_dielectric = 0.08 * self.specular * lerp(Vec3::new(1.0, 1.0, 1.0), self.tint(), self.specular_tint);
+        let fresnel = opaque * schlick(f0_dielectric, cos_d) + self.metallic * schlick(self.base_color, cos_d);
+        let d = self.distribution.d(wh) * self.distribution.g(wo, wi) / (4.0 * cos_o * cos_i);
+        f = f + d * fresnel;
+
+        //Clearcoat with a fixed index of refraction of 1.5
+        if self.clearcoat > 0.0 {
+            let coat = TrowbridgeReitz::new(CLEARCOAT_ALPHA, CLEARCOAT_ALPHA);
+            let fresnel = schlick(Vec3::new(0.04, 0.04, 0.04), cos_d);
+            f = f + (0.25 * self.clearcoat * coat.d(wh) * coat.g(wo, wi) / (4.0 * cos_o * cos_i)) * fresnel;
+        }
+        f
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let probabilities = self.lobe_probabilities(wo);
+        let mut pdf = probabilities[2] * self.distribution.pdf_dielectric(wo, wi, self.ior);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return pdf;
+        }
+        let wh = Vec3::unit_vector(wo + wi);
+        pdf += probabilities[0] * wi.z() / std::f32::consts::PI;
+        pdf += probabilities[1] * self.distribution.pdf(wo, wh) / (4.0 * wo.dot(wh));
+        let coat = TrowbridgeReitz::new(CLEARCOAT_ALPHA, CLEARCOAT_ALPHA);
+        pdf += probabilities[3] * coat.pdf(wo, wh) / (4.0 * wo.dot(wh));
+        pdf
+    }
+
+    fn sample(&self, wo: Vec3) -> Option<Vec3> {
+        let probabilities = self.lobe_probabilities(wo);
+        let u = sampler::random();
+        let (u1, u2) = (sampler::random(), sampler::random());
+        if u < probabilities[0] {
+            Some(random_cosine_direction(Vec3::new(0.0, 0.0, 1.0)))
+        } else if u < probabilities[0] + probabilities[1] {
+            Some(microfacet::reflect(wo, self.distribution.sample_wh(wo, u1, u2)))
+        } else if u < probabilities[0] + probabilities[1] + probabilities[2] {
+            self.distribution.sample_dielectric(wo, self.ior, u1, u2, sampler::random()).map(|(wi, _)| wi)
+        } else {
+            let coat = TrowbridgeReitz::new(CLEARCOAT_ALPHA, CLEARCOAT_ALPHA);
+            Some(microfacet::reflect(wo, coat.sample_wh(wo, u1, u2)))
+        }
+    }
+}
+
+impl Material for Principled {
+    //Lobes are sampled one at a time, the weight uses the combined density of all of them
+    fn scatter(&self, r: &Ray, _t: f32, point: Vec3, normal: Vec3, uv: TexCoord) -> Option<ScatterRecord> {
+        let shading = self.shading(point, uv);
+        let wo = -Vec3::unit_vector(r.direction());
+        let frame = Principled::frame(&shading, wo, outward_normal(normal, &uv));
+        let wo = frame.to_local(wo);
+        let wi = shading.sample(wo)?;
+
+        let pdf = shading.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: shading.eval(wo, wi) * (wi.z().abs() / pdf),
+            scattered: Ray::new(point, frame.to_world(wi)),
+            is_specular: false
+        })
+    }
+    fn emitted(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    fn eval(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> Vec3 {
+        let shading = self.shading(point, uv);
+        let frame = Principled::frame(&shading, wo, outward_normal(normal, &uv));
+        shading.eval(frame.to_local(wo), frame.to_local(wi))
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3, point: Vec3, normal: Vec3, uv: TexCoord) -> f32 {
+        let shading = self.shading(point, uv);
+        let frame = Principled::frame(&shading, wo, outward_normal(normal, &uv));
+        shading.pdf(frame.to_local(wo), frame.to_local(wi))
+    }
+}
@@ -0,0 +1,127 @@
+use vec3::Vec3;
+use texture::{Texture, TexCoord};
+
+//Texture coordinates generated from the position and normal of a lookup, for meshes without
+//authored ones. Projections work in the space the lookup arrives in, wrap the projection in a
+//TransformTexture to place, orient and scale it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    //Along z onto the xy plane
+    Planar,
+    //Around the y axis, u the angle and v the height
+    Cylindrical,
+    //Around the origin, u the longitude around the y axis and v the latitude, as on spheres
+    Spherical,
+    //Planar along the axis the normal is closest to
+    Box,
+    //Planar along all three axes, blended by the normal raised to the given sharpness
+    Triplanar(f32)
+}
+
+pub struct ProjectionTexture {
+    texture: Box<dyn Texture + Sync>,
+    projection: Projection
+}
+
+//Face of a box projection, the texture appears upright on the sides and unmirrored from outside
+fn box_face(p: Vec3, axis: usize, sign: f32) -> (f32, f32) {
+    match axis {
+        0 => (-sign * p.z(), p.y()),
+        1 => (p.x(), -sign * p.z()),
+        _ => (sign * p.x(), p.y())
+    }
+}
+
+fn dominant_axis(normal: Vec3) -> (usize, f32) {
+    let n = [normal.x(), normal.y(), normal.z()];
+    let axis = if n[0].abs() > n[1].abs() && n[0].abs() > n[2].abs() {
+        0
+    } else if n[1].abs() > n[2].abs() {
+        1
+    } else {
+        2
+    };
+    (axis, if n[axis] < 0.0 { -1.0 } else { 1.0 })
+}
+
+fn longitude(p: Vec3) -> f32 {
+    (p.z().atan2(-p.x()) + std::f32::consts::PI) / (2.0 * std::f32::consts::PI)
+}
+
+//uv from a mapping of positions, with derivatives from the position differentials. Angles wrap
+//around, so their differences are taken the short way.
+fn projected<F: Fn(Vec3) -> (f32, f32)>(uv: &TexCoord, p: Vec3, periodic_u: bool, map: F) -> TexCoord {
+    let (u, v) = map(p);
+    let difference = |dp: Vec3| {
+        let (du, dv) = map(p + dp);
+        let du = du - u;
+        (if periodic_u { du - du.round() } else { du }, dv - v)
+    };
+    let (dudx, dvdx) = difference(uv.dpdx);
+    let (dudy, dvdy) = difference(uv.dpdy);
+    TexCoord {
+        u,
+        v,
+        dudx,
+        dudy,
+        dvdx,
+        dvdy,
+        ..*uv
+    }
+}
+
+impl ProjectionTexture {
+    pub fn new(texture: Box<dyn Texture + Sync>, projection: Projection) -> ProjectionTexture {
+        ProjectionTexture {
+            texture,
+            projection
+        }
+    }
+
+    //Lookups without a normal, as from value, project along z
+    fn lookup(&self, uv: &TexCoord, p: Vec3) -> Vec3 {
+        match self.projection {
+            Projection::Planar => self.texture.filtered(&projected(uv, p, false, |p| (p.x(), p.y())), &p),
+            Projection::Cylindrical => self.texture.filtered(&projected(uv, p, true, |p| (longitude(p), p.y())), &p),
+            Projection::Spherical => {
+                let map = |p: Vec3| {
+                    let length = p.length();
+                    let theta = if length > 0.0 { (-p.y() / length).clamp(-1.0, 1.0).acos() } else { 0.0 };
+                    (longitude(p), theta / std::f32::consts::PI)
+                };
+                self.texture.filtered(&projected(uv, p, true, map), &p)
+            },
+            Projection::Box => {
+                let (axis, sign) = dominant_axis(uv.normal);
+                self.texture.filtered(&projected(uv, p, false, |p| box_face(p, axis, sign)), &p)
+            },
+            Projection::Triplanar(sharpness) => {
+                let n = uv.normal;
+                if n.squared_length() == 0.0 {
+                    return self.texture.filtered(&projected(uv, p, false, |p| box_face(p, 2, 1.0)), &p);
+                }
+                let weights = [n.x().abs().powf(sharpness), n.y().abs().powf(sharpness), n.z().abs().powf(sharpness)];
+                let signs = [n.x().signum(), n.y().signum(), n.z().signum()];
+                let total: f32 = weights.iter().sum();
+                let mut color = Vec3::zero_vector();
+                for axis in 0..3 {
+                    //Faces seen at grazing angles add little but cost a lookup each
+                    if weights[axis] > 0.001 * total {
+                        let face = projected(uv, p, false, |p| box_face(p, axis, signs[axis]));
+                        color = color + weights[axis] / total * self.texture.filtered(&face, &p);
+                    }
+                }
+                color
+            }
+        }
+    }
+}
+
+impl Texture for ProjectionTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.lookup(&TexCoord::new(u, v), *p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        self.lookup(uv, *p)
+    }
+}
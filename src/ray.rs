@@ -1,16 +1,43 @@
 use vec3::Vec3;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     origin: Vec3,
-    direction: Vec3
+    direction: Vec3,
+    differential: Option<RayDifferential>
+}
+
+//Rays through the neighbouring pixels along x and y, tracking the footprint of a camera ray
+//across the scene for texture filtering
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
         Ray {
             origin,
-            direction
+            direction,
+            differential: None
+        }
+    }
+
+    pub fn with_differential(self, differential: RayDifferential) -> Ray {
+        Ray {
+            differential: Some(differential),
+            ..self
+        }
+    }
+
+    //Continues the ray from a point on it, the differential rays stay as they are
+    pub fn with_origin(self, origin: Vec3) -> Ray {
+        Ray {
+            origin,
+            ..self
         }
     }
 
@@ -22,7 +49,63 @@ impl Ray {
         self.direction
     }
 
+    pub fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
+
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.origin + t * self.direction
     }
+
+    //Where the differential rays meet the plane through p with the given normal
+    pub fn differential_points(&self, p: Vec3, normal: Vec3) -> Option<(Vec3, Vec3)> {
+        let differential = self.differential?;
+        let offset = |origin: Vec3, direction: Vec3| {
+            let t = normal.dot(p - origin) / normal.dot(direction);
+            if t.is_finite() { Some(origin + t * direction) } else { None }
+        };
+        Some((offset(differential.rx_origin, differential.rx_direction)?, offset(differential.ry_origin, differential.ry_direction)?))
+    }
+
+    //scattered with the differentials of this ray after specular reflection or refraction at p.
+    //The differential rays leave the tangent plane like the main ray does, the relative index of
+    //refraction following from the angles of the main ray. Surface curvature is ignored.
+    pub fn specular_differential(&self, scattered: Ray, p: Vec3, normal: Vec3) -> Ray {
+        let (px, py) = match self.differential_points(p, normal) {
+            Some(points) => points,
+            None => return scattered
+        };
+        let differential = self.differential.unwrap();
+        let d = Vec3::unit_vector(self.direction);
+        let w = Vec3::unit_vector(scattered.direction());
+        //Normal facing the incoming ray
+        let n = if d.dot(normal) > 0.0 { -normal } else { normal };
+        let transmitted = w.dot(n) < 0.0;
+        let eta = if transmitted {
+            let sin_i = d.cross(n).length();
+            let sin_t = w.cross(n).length();
+            if sin_i > 0.001 { sin_t / sin_i } else { 1.0 }
+        } else {
+            1.0
+        };
+        let bend = |direction: Vec3| {
+            let v = Vec3::unit_vector(direction);
+            let cos_i = -v.dot(n);
+            if !transmitted {
+                return v + 2.0 * cos_i * n;
+            }
+            let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+            if k < 0.0 {
+                v + 2.0 * cos_i * n
+            } else {
+                eta * v + (eta * cos_i - k.sqrt()) * n
+            }
+        };
+        scattered.with_differential(RayDifferential {
+            rx_origin: px,
+            rx_direction: bend(differential.rx_direction),
+            ry_origin: py,
+            ry_direction: bend(differential.ry_direction)
+        })
+    }
 }
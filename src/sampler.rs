@@ -0,0 +1,31 @@
+use std::cell::RefCell;
+
+extern crate rand;
+
+//Every random number used while rendering is drawn through random(). By default these are
+//independent uniform samples, a sampler installed with scoped() can replace them, for example
+//to replay or mutate the sequence that produced a path.
+pub trait Sampler {
+    fn next_f32(&mut self) -> f32;
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Box<dyn Sampler>>> = RefCell::new(None);
+}
+
+pub fn random() -> f32 {
+    CURRENT.with(|current| {
+        match *current.borrow_mut() {
+            Some(ref mut sampler) => sampler.next_f32(),
+            None => rand::random::<f32>()
+        }
+    })
+}
+
+//Runs f with sampler providing all random numbers on this thread
+pub fn scoped<R, F: FnOnce() -> R>(sampler: Box<dyn Sampler>, f: F) -> R {
+    let previous = CURRENT.with(|current| current.replace(Some(sampler)));
+    let result = f();
+    CURRENT.with(|current| current.replace(previous));
+    result
+}
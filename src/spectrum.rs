@@ -0,0 +1,219 @@
+use vec3::Vec3;
+use sampler;
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+//Spectral rendering support. A spectral path carries three wavelengths in the channels of its
+//Vec3 values: a hero wavelength sampled uniformly over the visible range and two more at equal
+//offsets (Wilkie et al. 2014). RGB colors are upsampled to spectra with Smits' method and the
+//result is projected back to linear sRGB per sample, so the film always stores RGB.
+
+const LAMBDA_MIN: f32 = 360.0;
+const LAMBDA_MAX: f32 = 830.0;
+
+//Smits (1999) basis spectra, ten bins from 380 to 720nm
+const SMITS_MIN: f32 = 380.0;
+const SMITS_MAX: f32 = 720.0;
+const WHITE: [f32; 10] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+const CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0];
+const MAGENTA: [f32; 10] = [1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959];
+const YELLOW: [f32; 10] = [0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f32; 10] = [0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f32; 10] = [0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025];
+const BLUE: [f32; 10] = [1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496];
+
+thread_local! {
+    static HERO_WAVELENGTH: Cell<Option<f32>> = const { Cell::new(None) };
+}
+
+//Wavelengths of one path in nanometers, secondary ones are dropped after wavelength dependent
+//scattering such as dispersion
+#[derive(Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: [f32; 3],
+    active: usize
+}
+
+//Index of refraction as a function of wavelength
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    Constant(f32),
+    //n = a + b / lambda^2 with lambda in micrometers
+    Cauchy(f32, f32),
+    //n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i) with lambda in micrometers
+    Sellmeier([f32; 3], [f32; 3])
+}
+
+impl SampledWavelengths {
+    pub fn sample() -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + sampler::random() * range;
+        let rotate = |offset: f32| {
+            let lambda = hero + offset * range / 3.0;
+            if lambda > LAMBDA_MAX { lambda - range } else { lambda }
+        };
+        SampledWavelengths {
+            lambda: [hero, rotate(1.0), rotate(2.0)],
+            active: 3
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    //Keeps only the hero wavelength, values of the others must be discarded from now on
+    pub fn terminate_secondary(&mut self) {
+        self.active = 1;
+    }
+
+    //Masks the channels of dropped wavelengths
+    pub fn mask(&self, values: Vec3) -> Vec3 {
+        if self.active == 1 {
+            Vec3::new(values.x(), 0.0, 0.0)
+        } else {
+            values
+        }
+    }
+
+    //Spectral values of an RGB color at the sampled wavelengths
+    pub fn upsample(&self, rgb: Vec3) -> Vec3 {
+        self.mask(Vec3::new(smits(rgb, self.lambda[0]), smits(rgb, self.lambda[1]), smits(rgb, self.lambda[2])))
+    }
+
+    //Monte Carlo estimate of the color of spectral radiance sampled at these wavelengths,
+    //white balanced so that a flat spectrum gives white
+    pub fn to_rgb(self, values: Vec3) -> Vec3 {
+        let values = [values.x(), values.y(), values.z()];
+        let mut xyz = Vec3::zero_vector();
+        for (value, lambda) in values.iter().zip(self.lambda.iter()).take(self.active) {
+            xyz = xyz + *value * cie_xyz(*lambda);
+        }
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        xyz_to_srgb(xyz / (self.active as f32 * pdf)) * white_balance()
+    }
+}
+
+impl Dispersion {
+    //Schott N-BK7 crown glass
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier([1.039_612, 0.231_792_3, 1.010_469], [0.006_000_7, 0.020_017_9, 103.560_65])
+    }
+
+    pub fn fused_silica() -> Dispersion {
+        Dispersion::Sellmeier([0.696_166_3, 0.407_942_6, 0.897_479_4], [0.004_679_148, 0.013_512_063, 97.934])
+    }
+
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier([0.3306, 4.3356, 0.0], [0.030_625, 0.011_236, 0.0])
+    }
+
+    //Cauchy fit to the index at the d-line and the Abbe number (n_d - 1) / (n_F - n_C), as
+    //catalogs and lens prescriptions list glasses
+    pub fn abbe(n_d: f32, v_d: f32) -> Dispersion {
+        let (d, f, c) = (0.5876f32, 0.4861f32, 0.6563f32);
+        let b = (n_d - 1.0) / (v_d * (1.0 / (f * f) - 1.0 / (c * c)));
+        Dispersion::Cauchy(n_d - b / (d * d), b)
+    }
+
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l = wavelength / 1000.0;
+        let l2 = l * l;
+        match *self {
+            Dispersion::Constant(ior) => ior,
+            Dispersion::Cauchy(a, b) => a + b / l2,
+            Dispersion::Sellmeier(b, c) => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    //Index of refraction at the hero wavelength of the path being traced, at the sodium d-line
+    //outside of spectral rendering
+    pub fn current_ior(&self) -> f32 {
+        self.ior(hero_wavelength().unwrap_or(587.6))
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(*self, Dispersion::Constant(_))
+    }
+}
+
+//Hero wavelength of the spectral path traced on this thread
+pub fn hero_wavelength() -> Option<f32> {
+    HERO_WAVELENGTH.with(|hero| hero.get())
+}
+
+//Runs f with wavelength as the hero wavelength of this thread
+pub fn scoped<R, F: FnOnce() -> R>(wavelength: f32, f: F) -> R {
+    let previous = HERO_WAVELENGTH.with(|hero| hero.replace(Some(wavelength)));
+    let result = f();
+    HERO_WAVELENGTH.with(|hero| hero.set(previous));
+    result
+}
+
+fn basis(spectrum: &[f32; 10], wavelength: f32) -> f32 {
+    let bin = ((wavelength - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10.0).floor();
+    spectrum[(bin.max(0.0) as usize).min(9)]
+}
+
+fn smits(rgb: Vec3, wavelength: f32) -> f32 {
+    let (r, g, b) = (rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+    let at = |spectrum: &[f32; 10]| basis(spectrum, wavelength);
+    if r <= g && r <= b {
+        r * at(&WHITE) + if g <= b {
+            (g - r) * at(&CYAN) + (b - g) * at(&BLUE)
+        } else {
+            (b - r) * at(&CYAN) + (g - b) * at(&GREEN)
+        }
+    } else if g <= r && g <= b {
+        g * at(&WHITE) + if r <= b {
+            (r - g) * at(&MAGENTA) + (b - r) * at(&BLUE)
+        } else {
+            (b - g) * at(&MAGENTA) + (r - b) * at(&RED)
+        }
+    } else {
+        b * at(&WHITE) + if r <= g {
+            (r - b) * at(&YELLOW) + (g - r) * at(&GREEN)
+        } else {
+            (g - b) * at(&YELLOW) + (r - g) * at(&RED)
+        }
+    }
+}
+
+fn gaussian(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let sigma = if x < mu { sigma_low } else { sigma_high };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+//CIE 1931 color matching functions, multi-lobe fit of Wyman et al. 2013
+fn cie_xyz(wavelength: f32) -> Vec3 {
+    let x = 1.056 * gaussian(wavelength, 599.8, 37.9, 31.0) + 0.362 * gaussian(wavelength, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(wavelength, 501.1, 20.4, 26.2);
+    let y = 0.821 * gaussian(wavelength, 568.8, 46.9, 40.5) + 0.286 * gaussian(wavelength, 530.9, 16.3, 31.1);
+    let z = 1.217 * gaussian(wavelength, 437.0, 11.8, 36.0) + 0.681 * gaussian(wavelength, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+fn xyz_to_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+              -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+              0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z())
+}
+
+//Per channel scale mapping the color of a flat unit spectrum to white
+fn white_balance() -> Vec3 {
+    static WHITE_BALANCE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE_BALANCE.get_or_init(|| {
+        let mut xyz = Vec3::zero_vector();
+        let mut lambda = LAMBDA_MIN;
+        while lambda < LAMBDA_MAX {
+            xyz = xyz + cie_xyz(lambda + 0.5);
+            lambda += 1.0;
+        }
+        let white = xyz_to_srgb(xyz);
+        Vec3::new(1.0 / white.x(), 1.0 / white.y(), 1.0 / white.z())
+    })
+}
@@ -4,6 +4,9 @@ use ray::Ray;
 use material::Material;
 use hitable::Hit;
 use hitable::Hitable;
+use hitable::SurfaceSample;
+use material::random_unit_vector;
+use texture::TexCoord;
 
 pub struct Sphere {
     center: Vec3,
@@ -19,10 +22,33 @@ impl Sphere {
             material
         }
     }
+
+    //Longitude around the y axis as u and latitude from the south pole as v
+    fn texture_coordinates(normal: Vec3) -> TexCoord {
+        let pi = std::f32::consts::PI;
+        let phi = normal.z().atan2(-normal.x()) + pi;
+        let theta = (-normal.y()).clamp(-1.0, 1.0).acos();
+        TexCoord::new(phi / (2.0 * pi), theta / pi).with_normal(normal)
+    }
+
+    //Texture coordinates with their change towards the neighbouring pixels of r
+    fn differential_coordinates(&self, r: &Ray, normal: Vec3) -> TexCoord {
+        let pi = std::f32::consts::PI;
+        let uv = Sphere::texture_coordinates(normal);
+        //The parameterization is singular at the poles
+        let rho = (normal.x() * normal.x() + normal.z() * normal.z()).sqrt();
+        if rho < 1e-6 {
+            return uv;
+        }
+        let dpdu = 2.0 * pi * self.radius * Vec3::new(normal.z(), 0.0, -normal.x());
+        let cos_theta = -normal.y();
+        let dpdv = pi * self.radius * Vec3::new(cos_theta * normal.x() / rho, rho, cos_theta * normal.z() / rho);
+        uv.with_differentials(r, self.center + self.radius * normal, normal, dpdu, dpdv)
+    }
 }
 
 impl Hitable for Sphere {
-    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
         let oc = r.origin() - self.center;
         let a = r.direction().dot(r.direction());
         let b = oc.dot(r.direction());
@@ -31,20 +57,28 @@ impl Hitable for Sphere {
         if discriminant > 0.0 {
             let temp = (-b - discriminant.sqrt()) / a;
             if temp < t_max && temp > t_min {
+                let normal = (r.point_at_parameter(temp) - self.center) / self.radius;
                 return Some(Hit {
                     t: temp,
                     p: r.point_at_parameter(temp),
-                    normal: (r.point_at_parameter(temp) - self.center) / self.radius,
-                    material: &self.material
+                    normal,
+                    front_face: r.direction().dot(normal) < 0.0,
+                    material: &*self.material,
+                    medium_interface: None,
+                    uv: self.differential_coordinates(r, normal)
                 });
             }
             let temp = (-b + discriminant.sqrt()) / a;
             if temp < t_max && temp > t_min {
+                let normal = (r.point_at_parameter(temp) - self.center) / self.radius;
                 return Some(Hit {
                     t: temp,
                     p: r.point_at_parameter(temp),
-                    normal: (r.point_at_parameter(temp) - self.center) / self.radius,
-                    material: &self.material
+                    normal,
+                    front_face: r.direction().dot(normal) < 0.0,
+                    material: &*self.material,
+                    medium_interface: None,
+                    uv: self.differential_coordinates(r, normal)
                 });
             }
         }
@@ -54,4 +88,24 @@ impl Hitable for Sphere {
     fn bounding_box(&self) -> AABB {
         AABB::new(self.center - Vec3::new(self.radius, self.radius, self.radius), self.center + Vec3::new(self.radius, self.radius, self.radius))
     }
+
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self) -> Option<SurfaceSample<'_>> {
+        let normal = random_unit_vector();
+        Some(SurfaceSample {
+            p: self.center + self.radius * normal,
+            normal,
+            material: &*self.material,
+            uv: Sphere::texture_coordinates(normal)
+        })
+    }
+
+    fn emitters<'a>(&'a self, lights: &mut Vec<&'a (dyn Hitable + Sync)>) {
+        if self.material.is_emissive() {
+            lights.push(self);
+        }
+    }
 }
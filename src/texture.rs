@@ -1,7 +1,91 @@
 use vec3::Vec3;
+use ray::Ray;
+use hdr;
+use std::sync::Arc;
+use std::fs::File;
+use std::path::Path;
+
+extern crate png;
+
+//Surface parameterization of a shading point, where textures mapped onto surfaces are looked up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TexCoord {
+    pub u: f32,
+    pub v: f32,
+    //Change of u and v towards the neighbouring pixels, zero without ray differentials
+    pub dudx: f32,
+    pub dudy: f32,
+    pub dvdx: f32,
+    pub dvdy: f32,
+    //Change of the position towards the neighbouring pixels, for textures mapped by position
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    //Geometric normal of the surface, zero when there is none
+    pub normal: Vec3
+}
+
+impl TexCoord {
+    pub fn new(u: f32, v: f32) -> TexCoord {
+        TexCoord {
+            u,
+            v,
+            dudx: 0.0,
+            dudy: 0.0,
+            dvdx: 0.0,
+            dvdy: 0.0,
+            dpdx: Vec3::zero_vector(),
+            dpdy: Vec3::zero_vector(),
+            normal: Vec3::zero_vector()
+        }
+    }
+
+    pub fn with_normal(self, normal: Vec3) -> TexCoord {
+        TexCoord {
+            normal,
+            ..self
+        }
+    }
+
+    //Derivatives from where the differentials of r meet the tangent plane at p, dpdu and dpdv
+    //being the derivatives of the surface position. Least squares fit of dp = dpdu du + dpdv dv.
+    pub fn with_differentials(self, r: &Ray, p: Vec3, normal: Vec3, dpdu: Vec3, dpdv: Vec3) -> TexCoord {
+        let (px, py) = match r.differential_points(p, normal) {
+            Some(points) => points,
+            None => return self
+        };
+        let (dpdx, dpdy) = (px - p, py - p);
+        let (ata00, ata01, ata11) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+        let determinant = ata00 * ata11 - ata01 * ata01;
+        if determinant.abs() < 1e-12 {
+            return self;
+        }
+        let solve = |dp: Vec3| {
+            let (atb0, atb1) = (dpdu.dot(dp), dpdv.dot(dp));
+            let du = (ata11 * atb0 - ata01 * atb1) / determinant;
+            let dv = (ata00 * atb1 - ata01 * atb0) / determinant;
+            if du.is_finite() && dv.is_finite() { (du.clamp(-1e8, 1e8), dv.clamp(-1e8, 1e8)) } else { (0.0, 0.0) }
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        TexCoord {
+            dudx,
+            dudy,
+            dvdx,
+            dvdy,
+            dpdx,
+            dpdy,
+            ..self
+        }
+    }
+}
 
 pub trait Texture {
     fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+
+    //Value averaged over the footprint of uv, a point sample for textures without filtering
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        self.value(uv.u, uv.v, p)
+    }
 }
 
 pub struct ConstantTexture {
@@ -13,6 +97,159 @@ pub struct CheckerTexture {
     even: Box<dyn Texture + Sync>
 }
 
+//Checkerboard laid out in texture space, with frequency cells per unit of u and v
+pub struct UvCheckerTexture {
+    odd: Box<dyn Texture + Sync>,
+    even: Box<dyn Texture + Sync>,
+    frequency: (f32, f32)
+}
+
+//Checkerboard of cubes of side scale in world space, which moves with nothing but the world
+pub struct SolidCheckerTexture {
+    odd: Box<dyn Texture + Sync>,
+    even: Box<dyn Texture + Sync>,
+    scale: f32
+}
+
+//Lines along constant u and v over a background, width is the fraction of a cell covered by a line
+pub struct GridTexture {
+    line: Box<dyn Texture + Sync>,
+    background: Box<dyn Texture + Sync>,
+    frequency: (f32, f32),
+    width: f32
+}
+
+//Shows the parameterization of a surface: u in red and v in green over alternating light and
+//dark cells, with grid lines at cell borders. Texture coordinates outside [0, 1] get a blue tint,
+//so stretching, seams, flipped and overlapping islands all stand out.
+pub struct UvDebugTexture {
+    frequency: (f32, f32)
+}
+
+//Texture multiplied by a constant factor, then offset
+pub struct ScaledTexture {
+    texture: Box<dyn Texture + Sync>,
+    scale: Vec3,
+    offset: Vec3
+}
+
+//Product of two textures
+pub struct MultiplyTexture {
+    first: Box<dyn Texture + Sync>,
+    second: Box<dyn Texture + Sync>
+}
+
+//Sum of two textures
+pub struct AddTexture {
+    first: Box<dyn Texture + Sync>,
+    second: Box<dyn Texture + Sync>
+}
+
+//Linear blend of two textures by the first channel of a mask
+pub struct MixTexture {
+    first: Box<dyn Texture + Sync>,
+    second: Box<dyn Texture + Sync>,
+    mask: Box<dyn Texture + Sync>
+}
+
+//First channel of a texture mapped through a color ramp
+pub struct RampTexture {
+    source: Box<dyn Texture + Sync>,
+    ramp: ColorRamp
+}
+
+//Texture looked up at affinely transformed points, transformations apply in the order they are
+//added
+pub struct TransformTexture {
+    texture: Box<dyn Texture + Sync>,
+    rows: [Vec3; 3],
+    translation: Vec3
+}
+
+//Texture looked up at points displaced by a vector valued texture, remapped from [0, 1] to
+//[-amount, amount] per axis
+pub struct WarpTexture {
+    texture: Box<dyn Texture + Sync>,
+    warp: Box<dyn Texture + Sync>,
+    amount: f32
+}
+
+//Piecewise linear map from [0, 1] to colors
+#[derive(Clone)]
+pub struct ColorRamp {
+    stops: Vec<(f32, Vec3)>
+}
+
+//Filtering of image textures over the footprint of a lookup
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    //Bilinear lookup in the full resolution image
+    Bilinear,
+    //Bilinear lookups in the two MIP levels closest to the footprint width, blended
+    Trilinear,
+    //Gaussian weighted average over the elliptical footprint (Heckbert 1989), which keeps
+    //detail along the minor axis of surfaces seen at grazing angles
+    Ewa
+}
+
+//One level of a MIP pyramid
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>
+}
+
+//PNG or floating point image looked up by uv, repeating outside [0, 1]. v = 0 is the bottom row as in OBJ files.
+//Filtered lookups select from a MIP pyramid by the footprint of the texture coordinates.
+pub struct ImageTexture {
+    //From the full resolution image down to a single texel
+    levels: Vec<MipLevel>,
+    filter: TextureFilter
+}
+
+impl ColorRamp {
+    //Stops are positions in [0, 1] with their colors, in any order
+    pub fn new(stops: Vec<(f32, Vec3)>) -> ColorRamp {
+        let mut stops = stops;
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        assert!(!stops.is_empty(), "A color ramp needs at least one stop!");
+        ColorRamp {
+            stops
+        }
+    }
+
+    pub fn between(low: Vec3, high: Vec3) -> ColorRamp {
+        ColorRamp::new(vec![(0.0, low), (1.0, high)])
+    }
+
+    pub fn marble() -> ColorRamp {
+        ColorRamp::new(vec![(0.0, Vec3::new(0.25, 0.25, 0.3)), (0.3, Vec3::new(0.7, 0.7, 0.72)), (1.0, Vec3::new(0.92, 0.91, 0.88))])
+    }
+
+    pub fn wood() -> ColorRamp {
+        ColorRamp::new(vec![(0.0, Vec3::new(0.45, 0.25, 0.1)), (0.7, Vec3::new(0.6, 0.38, 0.17)), (1.0, Vec3::new(0.3, 0.15, 0.05))])
+    }
+
+    pub fn granite() -> ColorRamp {
+        ColorRamp::new(vec![(0.0, Vec3::new(0.05, 0.05, 0.05)), (0.25, Vec3::new(0.45, 0.4, 0.38)), (0.6, Vec3::new(0.7, 0.6, 0.55)), (1.0, Vec3::new(0.9, 0.88, 0.85))])
+    }
+
+    pub fn value(&self, t: f32) -> Vec3 {
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let index = self.stops.iter().position(|stop| stop.0 > t).unwrap();
+        let (t0, c0) = self.stops[index - 1];
+        let (t1, c1) = self.stops[index];
+        let f = if t1 > t0 { (t - t0) / (t1 - t0) } else { 0.0 };
+        (1.0 - f) * c0 + f * c1
+    }
+}
 
 impl ConstantTexture {
     pub fn new(color: Vec3) -> ConstantTexture {
@@ -22,6 +259,16 @@ impl ConstantTexture {
     }
 }
 
+//Shared textures can be used wherever an owned one is expected
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        (**self).value(u, v, p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        (**self).filtered(uv, p)
+    }
+}
+
 impl Texture for ConstantTexture {
     fn value(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
         self.color
@@ -47,3 +294,566 @@ impl Texture for CheckerTexture {
         }
     }
 }
+
+impl UvCheckerTexture {
+    pub fn new(odd: Box<dyn Texture + Sync>, even: Box<dyn Texture + Sync>) -> UvCheckerTexture {
+        UvCheckerTexture {
+            odd,
+            even,
+            frequency: (8.0, 8.0)
+        }
+    }
+
+    pub fn with_frequency(self, u: f32, v: f32) -> UvCheckerTexture {
+        UvCheckerTexture {
+            frequency: (u, v),
+            ..self
+        }
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        if is_odd_cell(&[u * self.frequency.0, v * self.frequency.1]) {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+impl SolidCheckerTexture {
+    pub fn new(odd: Box<dyn Texture + Sync>, even: Box<dyn Texture + Sync>, scale: f32) -> SolidCheckerTexture {
+        SolidCheckerTexture {
+            odd,
+            even,
+            scale
+        }
+    }
+}
+
+impl Texture for SolidCheckerTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        if is_odd_cell(&[p.x() / self.scale, p.y() / self.scale, p.z() / self.scale]) {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+impl GridTexture {
+    pub fn new(line: Box<dyn Texture + Sync>, background: Box<dyn Texture + Sync>) -> GridTexture {
+        GridTexture {
+            line,
+            background,
+            frequency: (8.0, 8.0),
+            width: 0.05
+        }
+    }
+
+    pub fn with_frequency(self, u: f32, v: f32) -> GridTexture {
+        GridTexture {
+            frequency: (u, v),
+            ..self
+        }
+    }
+
+    pub fn with_width(self, width: f32) -> GridTexture {
+        GridTexture {
+            width,
+            ..self
+        }
+    }
+}
+
+impl Texture for GridTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        if on_line(u * self.frequency.0, self.width) || on_line(v * self.frequency.1, self.width) {
+            self.line.value(u, v, p)
+        } else {
+            self.background.value(u, v, p)
+        }
+    }
+}
+
+impl UvDebugTexture {
+    pub fn new() -> UvDebugTexture {
+        UvDebugTexture {
+            frequency: (8.0, 8.0)
+        }
+    }
+
+    pub fn with_frequency(self, u: f32, v: f32) -> UvDebugTexture {
+        UvDebugTexture {
+            frequency: (u, v)
+        }
+    }
+}
+
+impl Default for UvDebugTexture {
+    fn default() -> UvDebugTexture {
+        UvDebugTexture::new()
+    }
+}
+
+impl Texture for UvDebugTexture {
+    fn value(&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        let (cell_u, cell_v) = (u * self.frequency.0, v * self.frequency.1);
+        if on_line(cell_u, 0.04) || on_line(cell_v, 0.04) {
+            return Vec3::new(0.05, 0.05, 0.05);
+        }
+        let outside = !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v);
+        let color = Vec3::new(u - u.floor(), v - v.floor(), if outside { 0.8 } else { 0.1 });
+        let brightness = if is_odd_cell(&[cell_u, cell_v]) { 0.6 } else { 1.0 };
+        brightness * color
+    }
+}
+
+//Parity of the integer cell containing a point, in any number of dimensions
+fn is_odd_cell(coordinates: &[f32]) -> bool {
+    coordinates.iter().map(|c| c.floor() as i64).sum::<i64>().rem_euclid(2) == 1
+}
+
+//Whether a coordinate lies within width / 2 of an integer
+fn on_line(coordinate: f32, width: f32) -> bool {
+    let fraction = coordinate - coordinate.floor();
+    fraction < 0.5 * width || fraction > 1.0 - 0.5 * width
+}
+
+impl ScaledTexture {
+    pub fn new(texture: Box<dyn Texture + Sync>, scale: Vec3) -> ScaledTexture {
+        ScaledTexture {
+            texture,
+            scale,
+            offset: Vec3::zero_vector()
+        }
+    }
+
+    pub fn with_offset(self, offset: Vec3) -> ScaledTexture {
+        ScaledTexture {
+            offset,
+            ..self
+        }
+    }
+}
+
+impl Texture for ScaledTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.texture.value(u, v, p) * self.scale + self.offset
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        self.texture.filtered(uv, p) * self.scale + self.offset
+    }
+}
+
+impl MultiplyTexture {
+    pub fn new(first: Box<dyn Texture + Sync>, second: Box<dyn Texture + Sync>) -> MultiplyTexture {
+        MultiplyTexture {
+            first,
+            second
+        }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.first.value(u, v, p) * self.second.value(u, v, p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        self.first.filtered(uv, p) * self.second.filtered(uv, p)
+    }
+}
+
+impl AddTexture {
+    pub fn new(first: Box<dyn Texture + Sync>, second: Box<dyn Texture + Sync>) -> AddTexture {
+        AddTexture {
+            first,
+            second
+        }
+    }
+}
+
+impl Texture for AddTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.first.value(u, v, p) + self.second.value(u, v, p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        self.first.filtered(uv, p) + self.second.filtered(uv, p)
+    }
+}
+
+impl MixTexture {
+    pub fn new(first: Box<dyn Texture + Sync>, second: Box<dyn Texture + Sync>, mask: Box<dyn Texture + Sync>) -> MixTexture {
+        MixTexture {
+            first,
+            second,
+            mask
+        }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let t = self.mask.value(u, v, p).x().clamp(0.0, 1.0);
+        (1.0 - t) * self.first.value(u, v, p) + t * self.second.value(u, v, p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        let t = self.mask.filtered(uv, p).x().clamp(0.0, 1.0);
+        (1.0 - t) * self.first.filtered(uv, p) + t * self.second.filtered(uv, p)
+    }
+}
+
+impl RampTexture {
+    pub fn new(source: Box<dyn Texture + Sync>, ramp: ColorRamp) -> RampTexture {
+        RampTexture {
+            source,
+            ramp
+        }
+    }
+}
+
+impl Texture for RampTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        self.ramp.value(self.source.value(u, v, p).x())
+    }
+}
+
+impl TransformTexture {
+    pub fn new(texture: Box<dyn Texture + Sync>) -> TransformTexture {
+        TransformTexture {
+            texture,
+            rows: [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)],
+            translation: Vec3::zero_vector()
+        }
+    }
+
+    //Applies the linear map with the given rows after the current transformation
+    fn then(self, rows: [Vec3; 3]) -> TransformTexture {
+        let current = self.rows;
+        let product = |row: Vec3| row.x() * current[0] + row.y() * current[1] + row.z() * current[2];
+        TransformTexture {
+            rows: [product(rows[0]), product(rows[1]), product(rows[2])],
+            translation: Vec3::new(rows[0].dot(self.translation), rows[1].dot(self.translation), rows[2].dot(self.translation)),
+            ..self
+        }
+    }
+
+    pub fn scale(self, scale: Vec3) -> TransformTexture {
+        self.then([Vec3::new(scale.x(), 0.0, 0.0), Vec3::new(0.0, scale.y(), 0.0), Vec3::new(0.0, 0.0, scale.z())])
+    }
+
+    //Rotation about an axis through the origin, counterclockwise looking down the axis
+    pub fn rotate(self, axis: Vec3, degrees: f32) -> TransformTexture {
+        let a = Vec3::unit_vector(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let c = 1.0 - cos;
+        self.then([Vec3::new(cos + a.x() * a.x() * c, a.x() * a.y() * c - a.z() * sin, a.x() * a.z() * c + a.y() * sin),
+                   Vec3::new(a.y() * a.x() * c + a.z() * sin, cos + a.y() * a.y() * c, a.y() * a.z() * c - a.x() * sin),
+                   Vec3::new(a.z() * a.x() * c - a.y() * sin, a.z() * a.y() * c + a.x() * sin, cos + a.z() * a.z() * c)])
+    }
+
+    pub fn translate(self, offset: Vec3) -> TransformTexture {
+        TransformTexture {
+            translation: self.translation + offset,
+            ..self
+        }
+    }
+}
+
+impl TransformTexture {
+    fn linear(&self, v: Vec3) -> Vec3 {
+        Vec3::new(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+
+    //Normals transform with the inverse transpose, the cofactor matrix up to a scale
+    fn normal(&self, n: Vec3) -> Vec3 {
+        let [r0, r1, r2] = self.rows;
+        let n = Vec3::new(r1.cross(r2).dot(n), r2.cross(r0).dot(n), r0.cross(r1).dot(n));
+        let determinant = r0.dot(r1.cross(r2));
+        if n.squared_length() == 0.0 {
+            n
+        } else if determinant < 0.0 {
+            -Vec3::unit_vector(n)
+        } else {
+            Vec3::unit_vector(n)
+        }
+    }
+}
+
+impl Texture for TransformTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let p = self.linear(*p) + self.translation;
+        self.texture.value(u, v, &p)
+    }
+    fn filtered(&self, uv: &TexCoord, p: &Vec3) -> Vec3 {
+        let p = self.linear(*p) + self.translation;
+        let uv = TexCoord {
+            dpdx: self.linear(uv.dpdx),
+            dpdy: self.linear(uv.dpdy),
+            normal: self.normal(uv.normal),
+            ..*uv
+        };
+        self.texture.filtered(&uv, &p)
+    }
+}
+
+impl WarpTexture {
+    pub fn new(texture: Box<dyn Texture + Sync>, warp: Box<dyn Texture + Sync>, amount: f32) -> WarpTexture {
+        WarpTexture {
+            texture,
+            warp,
+            amount
+        }
+    }
+}
+
+impl Texture for WarpTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let offset = self.warp.value(u, v, p) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+        self.texture.value(u, v, &(*p + self.amount * offset))
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl ImageTexture {
+    //Color PNG images are stored in sRGB, data like normal and height maps are linear. Radiance,
+    //PFM and OpenEXR images hold linear floating point values.
+    pub fn load(path: &Path, srgb: bool) -> Result<ImageTexture, String> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase());
+        let float = match extension.as_deref() {
+            Some("hdr") | Some("pic") => Some(hdr::load_radiance(path)),
+            Some("pfm") => Some(hdr::load_pfm(path)),
+            Some("exr") => Some(hdr::load_exr(path)),
+            _ => None
+        };
+        if let Some(float) = float {
+            let (width, height, pixels) = float?;
+            return Ok(ImageTexture::from_pixels(width, height, pixels));
+        }
+        let decode = |byte: u8| {
+            let value = byte as f32 / 255.0;
+            if srgb { srgb_to_linear(value) } else { value }
+        };
+        ImageTexture::decode(path, |pixel: &[u8]| if pixel.len() < 3 {
+            let value = decode(pixel[0]);
+            Vec3::new(value, value, value)
+        } else {
+            Vec3::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2]))
+        })
+    }
+
+    //Alpha channel times scale in all channels, or the gray level of images without one
+    pub fn load_opacity(path: &Path, scale: f32) -> Result<ImageTexture, String> {
+        ImageTexture::decode(path, |pixel: &[u8]| {
+            let alpha = scale * match pixel.len() {
+                2 => pixel[1],
+                4 => pixel[3],
+                _ => pixel[0]
+            } as f32 / 255.0;
+            Vec3::new(alpha, alpha, alpha)
+        })
+    }
+
+    fn decode<F: Fn(&[u8]) -> Vec3>(path: &Path, convert: F) -> Result<ImageTexture, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let file = File::open(path).map_err(|e| error(&e))?;
+        let (info, mut reader) = png::Decoder::new(file).read_info().map_err(|e| error(&e))?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(|e| error(&e))?;
+
+        let channels = match reader.output_color_type().0 {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return Err(error(&"unexpanded palette"))
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &data[y * info.line_size..];
+            for x in 0..width {
+                pixels.push(convert(&row[x * channels..(x + 1) * channels]));
+            }
+        }
+        Ok(ImageTexture::from_pixels(width, height, pixels))
+    }
+
+    //Pixels row by row from the top
+    fn from_pixels(width: usize, height: usize, pixels: Vec<Vec3>) -> ImageTexture {
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            pixels
+        }];
+        while levels[levels.len() - 1].width > 1 || levels[levels.len() - 1].height > 1 {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+        ImageTexture {
+            levels,
+            filter: TextureFilter::Ewa
+        }
+    }
+
+    pub fn with_filter(self, filter: TextureFilter) -> ImageTexture {
+        ImageTexture {
+            filter,
+            ..self
+        }
+    }
+
+    //Full resolution pixels row by row from the top
+    pub fn pixels(&self) -> (usize, usize, &[Vec3]) {
+        (self.levels[0].width, self.levels[0].height, &self.levels[0].pixels)
+    }
+
+    //Continuous level whose texels are as wide as width in texture coordinates
+    fn level_of_detail(&self, width: f32) -> f32 {
+        let resolution = self.levels[0].width.max(self.levels[0].height) as f32;
+        (width * resolution).max(1e-8).log2().clamp(0.0, (self.levels.len() - 1) as f32)
+    }
+
+    fn trilinear(&self, uv: &TexCoord) -> Vec3 {
+        let width = 2.0 * uv.dudx.abs().max(uv.dudy.abs()).max(uv.dvdx.abs()).max(uv.dvdy.abs());
+        let lod = self.level_of_detail(width);
+        let level = lod.floor() as usize;
+        if level + 1 >= self.levels.len() {
+            return self.levels[level].bilinear(uv.u, uv.v);
+        }
+        let t = lod - level as f32;
+        (1.0 - t) * self.levels[level].bilinear(uv.u, uv.v) + t * self.levels[level + 1].bilinear(uv.u, uv.v)
+    }
+
+    fn ewa(&self, uv: &TexCoord) -> Vec3 {
+        const MAX_ANISOTROPY: f32 = 8.0;
+        let (mut major, mut minor) = ((uv.dudx, uv.dvdx), (uv.dudy, uv.dvdy));
+        let length = |axis: (f32, f32)| (axis.0 * axis.0 + axis.1 * axis.1).sqrt();
+        if length(major) < length(minor) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        //Very eccentric ellipses are widened, which blurs but bounds the texels visited
+        let (major_length, minor_length) = (length(major), length(minor));
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+        }
+        if length(minor) == 0.0 {
+            return self.levels[0].bilinear(uv.u, uv.v);
+        }
+        let lod = self.level_of_detail(length(minor));
+        let level = lod.floor() as usize;
+        if level + 1 >= self.levels.len() {
+            return self.levels[level].ewa(uv.u, uv.v, major, minor);
+        }
+        let t = lod - level as f32;
+        (1.0 - t) * self.levels[level].ewa(uv.u, uv.v, major, minor) + t * self.levels[level + 1].ewa(uv.u, uv.v, major, minor)
+    }
+}
+
+impl MipLevel {
+    //Box filtered level of half the resolution, odd sizes spread the extra texels over the
+    //neighbouring ones
+    fn downsample(&self) -> MipLevel {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let span = |i: usize, size: usize, next: usize| {
+            let start = i * size / next;
+            start..((i + 1) * size / next).max(start + 1)
+        };
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3::zero_vector();
+                let mut count = 0;
+                for sy in span(y, self.height, height) {
+                    for sx in span(x, self.width, width) {
+                        sum = sum + self.pixels[sy * self.width + sx];
+                        count += 1;
+                    }
+                }
+                pixels.push(sum / count as f32);
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            pixels
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    fn bilinear(&self, u: f32, v: f32) -> Vec3 {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - fy) * ((1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0))
+            + fy * ((1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1))
+    }
+
+    //Average over the ellipse spanned by two axes in texture coordinates, following pbrt
+    fn ewa(&self, u: f32, v: f32, axis0: (f32, f32), axis1: (f32, f32)) -> Vec3 {
+        const ALPHA: f32 = 2.0;
+        let (width, height) = (self.width as f32, self.height as f32);
+        //Texel space, where y grows downwards
+        let (x, y) = (u * width - 0.5, (1.0 - v) * height - 0.5);
+        let (dx0, dy0) = (axis0.0 * width, -axis0.1 * height);
+        let (dx1, dy1) = (axis1.0 * width, -axis1.1 * height);
+        //Implicit ellipse a x^2 + b x y + c y^2 < 1, grown by a texel so it covers one at least
+        let a = dy0 * dy0 + dy1 * dy1 + 1.0;
+        let b = -2.0 * (dx0 * dy0 + dx1 * dy1);
+        let c = dx0 * dx0 + dx1 * dx1 + 1.0;
+        let inverse_f = 1.0 / (a * c - 0.25 * b * b);
+        let (a, b, c) = (a * inverse_f, b * inverse_f, c * inverse_f);
+        let determinant = 4.0 * a * c - b * b;
+        let extent_x = 2.0 * (determinant * c).sqrt() / determinant;
+        let extent_y = 2.0 * (determinant * a).sqrt() / determinant;
+
+        let mut sum = Vec3::zero_vector();
+        let mut weights = 0.0;
+        for ty in (y - extent_y).ceil() as i64..=(y + extent_y).floor() as i64 {
+            let dy = ty as f32 - y;
+            for tx in (x - extent_x).ceil() as i64..=(x + extent_x).floor() as i64 {
+                let dx = tx as f32 - x;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum = sum + weight * self.texel(tx, ty);
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0.0 {
+            sum / weights
+        } else {
+            self.bilinear(u, v)
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        self.levels[0].bilinear(u, v)
+    }
+    fn filtered(&self, uv: &TexCoord, _p: &Vec3) -> Vec3 {
+        match self.filter {
+            TextureFilter::Bilinear => self.levels[0].bilinear(uv.u, uv.v),
+            TextureFilter::Trilinear => self.trilinear(uv),
+            TextureFilter::Ewa => self.ewa(uv)
+        }
+    }
+}
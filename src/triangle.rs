@@ -4,13 +4,24 @@ use aabb::AABB;
 use material::Material;
 use hitable::Hitable;
 use hitable::Hit;
+use hitable::SurfaceSample;
+use sampler;
+use bump::BumpMap;
+use texture::{Texture, TexCoord};
+use std::sync::Arc;
 
 pub struct Triangle {
     p1: Vec3,
     p2: Vec3,
     p3: Vec3,
     normal: Vec3,
-    material: Box<dyn Material + Sync>
+    material: Box<dyn Material + Sync>,
+    //Texture coordinates of the vertices and the surface derivatives they define
+    uvs: [(f32, f32); 3],
+    dpdu: Vec3,
+    dpdv: Vec3,
+    bump: Option<Arc<BumpMap>>,
+    opacity: Option<Arc<dyn Texture + Send + Sync>>
 }
 
 impl Triangle {
@@ -20,13 +31,73 @@ impl Triangle {
             p2,
             p3,
             normal,
-            material
+            material,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            dpdu: p2 - p1,
+            dpdv: p3 - p1,
+            bump: None,
+            opacity: None
         }
     }
+
+    pub fn with_uvs(self, uv1: (f32, f32), uv2: (f32, f32), uv3: (f32, f32)) -> Triangle {
+        let (du1, dv1) = (uv2.0 - uv1.0, uv2.1 - uv1.1);
+        let (du2, dv2) = (uv3.0 - uv1.0, uv3.1 - uv1.1);
+        let determinant = du1 * dv2 - dv1 * du2;
+        //Degenerate texture coordinates get an arbitrary tangent frame
+        let (dpdu, dpdv) = if determinant.abs() < 1e-9 {
+            self.normal.coordinate_system()
+        } else {
+            let edge1 = self.p2 - self.p1;
+            let edge2 = self.p3 - self.p1;
+            ((dv2 * edge1 - dv1 * edge2) / determinant, (du1 * edge2 - du2 * edge1) / determinant)
+        };
+        Triangle {
+            uvs: [uv1, uv2, uv3],
+            dpdu,
+            dpdv,
+            ..self
+        }
+    }
+
+    pub fn with_bump(self, bump: Arc<BumpMap>) -> Triangle {
+        Triangle {
+            bump: Some(bump),
+            ..self
+        }
+    }
+
+    //Opacity read from the first channel. Hits on transparent texels are ignored, partially
+    //transparent ones are hit with a probability of their opacity.
+    pub fn with_opacity(self, opacity: Arc<dyn Texture + Send + Sync>) -> Triangle {
+        Triangle {
+            opacity: Some(opacity),
+            ..self
+        }
+    }
+
+    fn texture_coordinates(&self, b1: f32, b2: f32) -> (f32, f32) {
+        let b0 = 1.0 - b1 - b2;
+        (b0 * self.uvs[0].0 + b1 * self.uvs[1].0 + b2 * self.uvs[2].0,
+         b0 * self.uvs[0].1 + b1 * self.uvs[1].1 + b2 * self.uvs[2].1)
+    }
+}
+
+//Uniform number in [0, 1) derived from a ray, so that the same ray always makes the same
+//stochastic opacity decision, regardless of the order primitives are tested in
+fn hash_ray(r: &Ray, t: f32) -> f32 {
+    let values = [r.origin().x(), r.origin().y(), r.origin().z(), r.direction().x(), r.direction().y(), r.direction().z(), t];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for value in values.iter() {
+        hash ^= value.to_bits() as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        hash ^= hash >> 29;
+    }
+    (hash >> 40) as f32 / (1u64 << 24) as f32
 }
 
 impl Hitable for Triangle {
-    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit<'_>> {
         const EPSILON: f32 = 0.0000001;
         let edge1 = self.p2 - self.p1;
         let edge2 = self.p3 - self.p1;
@@ -41,7 +112,7 @@ impl Hitable for Triangle {
         let f = 1.0 / a;
         let s = r.origin() - self.p1;
         let u = f * s.dot(h);
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
@@ -52,17 +123,33 @@ impl Hitable for Triangle {
         }
 
         let t = f * edge2.dot(q);
-        if t > EPSILON && t < t_max && t > t_min {
-            let normal = if r.direction().dot(self.normal) >= 0.0 {
-                -1.0 * self.normal
+        if t < t_max && t > t_min {
+            let p = r.origin() + t * r.direction();
+            let (tex_u, tex_v) = self.texture_coordinates(u, v);
+            if let Some(ref opacity) = self.opacity {
+                let alpha = opacity.value(tex_u, tex_v, &p).x();
+                if alpha <= 0.0 || (alpha < 1.0 && hash_ray(r, t) >= alpha) {
+                    return None;
+                }
+            }
+            let front_face = r.direction().dot(self.normal) < 0.0;
+            let normal = match self.bump {
+                Some(ref bump) => bump.shading_normal(tex_u, tex_v, p, self.normal, self.dpdu, self.dpdv),
+                None => self.normal
+            };
+            let normal = if front_face {
+                normal
             } else {
-                self.normal
+                -1.0 * normal
             };
             return Some(Hit {
                 t,
-                p: r.origin() + t * r.direction(),
+                p,
                 normal,
-                material: &self.material
+                front_face,
+                material: &*self.material,
+                medium_interface: None,
+                uv: TexCoord::new(tex_u, tex_v).with_normal(self.normal).with_differentials(r, p, self.normal, self.dpdu, self.dpdv)
             });
         }
 
@@ -80,4 +167,25 @@ impl Hitable for Triangle {
         let min = Vec3::new(min_x - delta, min_y - delta, min_z - delta);
         AABB::new(min, max)
     }
+    fn area(&self) -> f32 {
+        0.5 * (self.p2 - self.p1).cross(self.p3 - self.p1).length()
+    }
+    fn sample_surface(&self) -> Option<SurfaceSample<'_>> {
+        let su = sampler::random().sqrt();
+        let r2 = sampler::random();
+        let b1 = su * (1.0 - r2);
+        let b2 = su * r2;
+        let (u, v) = self.texture_coordinates(b1, b2);
+        Some(SurfaceSample {
+            p: self.p1 + b1 * (self.p2 - self.p1) + b2 * (self.p3 - self.p1),
+            normal: self.normal,
+            material: &*self.material,
+            uv: TexCoord::new(u, v).with_normal(self.normal)
+        })
+    }
+    fn emitters<'a>(&'a self, lights: &mut Vec<&'a (dyn Hitable + Sync)>) {
+        if self.material.is_emissive() {
+            lights.push(self);
+        }
+    }
 }
\ No newline at end of file
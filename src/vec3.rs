@@ -1,6 +1,6 @@
 use std::ops;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
     x: f32,
     y: f32,
@@ -58,6 +58,20 @@ impl Vec3 {
             z: self.x*v2.y - self.y*v2.x
         }
     }
+    //Two unit vectors forming an orthonormal basis with this unit vector (Duff et al. 2017)
+    pub fn coordinate_system(&self) -> (Vec3, Vec3) {
+        let sign = 1.0_f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+         Vec3::new(b, sign + self.y * self.y * a, -self.y))
+    }
+    pub fn exp(&self) -> Vec3 {
+        Vec3::new(self.x.exp(), self.y.exp(), self.z.exp())
+    }
+    pub fn max_component(&self) -> f32 {
+        self.x.max(self.y.max(self.z))
+    }
     pub fn clamp(&self, min: Vec3, max: Vec3) -> Vec3 {
         let x = if self.x > max.x {
             max.x
@@ -109,6 +123,18 @@ impl ops::Sub<Vec3> for Vec3 {
     }
 }
 
+impl ops::Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z
+        }
+    }
+}
+
 impl ops::Mul<f32> for Vec3 {
     type Output = Vec3;
 